@@ -1,9 +1,18 @@
 CREATE TABLE IF NOT EXISTS users (
   id SERIAL PRIMARY KEY,
   username VARCHAR(64) NOT NULL UNIQUE,
-  password VARCHAR(64) NOT NULL,
+  password VARCHAR(255) NOT NULL,
   deleted_at TIMESTAMPTZ DEFAULT NULL,
-  token TEXT DEFAULT NULL
+  token TEXT DEFAULT NULL,
+  role VARCHAR(32) NOT NULL DEFAULT 'user',
+  verified_at TIMESTAMPTZ DEFAULT NULL,
+  totp_secret TEXT DEFAULT NULL,
+  totp_enabled_at TIMESTAMPTZ DEFAULT NULL,
+  totp_last_step BIGINT DEFAULT NULL,
+  display_name VARCHAR(64) DEFAULT NULL,
+  timezone VARCHAR(64) DEFAULT NULL,
+  locale VARCHAR(35) DEFAULT NULL,
+  disabled_at TIMESTAMPTZ DEFAULT NULL
 );
 
 CREATE TABLE IF NOT EXISTS tasks (
@@ -18,12 +27,111 @@ CREATE TABLE IF NOT EXISTS tasks (
   CONSTRAINT fk_users FOREIGN KEY (user_id) REFERENCES users(id)
 );
 
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  family UUID NOT NULL,
+  token_hash VARCHAR(64) NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ DEFAULT NULL,
+  revoked_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CONSTRAINT fk_refresh_tokens_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+  id SERIAL PRIMARY KEY,
+  jti UUID DEFAULT NULL UNIQUE,
+  user_id INTEGER NOT NULL,
+  revoked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL,
+  except_sid INTEGER DEFAULT NULL,
+  CONSTRAINT fk_revoked_tokens_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  code_hash VARCHAR(64) NOT NULL,
+  used_at TIMESTAMPTZ DEFAULT NULL,
+  CONSTRAINT fk_recovery_codes_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS user_tokens (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  purpose VARCHAR(32) NOT NULL,
+  token_hash VARCHAR(64) NOT NULL UNIQUE,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CONSTRAINT fk_user_tokens_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  key_hash VARCHAR(64) NOT NULL UNIQUE,
+  scopes TEXT NOT NULL,
+  expires_at TIMESTAMPTZ DEFAULT NULL,
+  last_used_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CONSTRAINT fk_api_keys_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS user_identities (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  issuer VARCHAR(255) NOT NULL,
+  subject VARCHAR(255) NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CONSTRAINT uq_user_identities_subject UNIQUE (issuer, subject),
+  CONSTRAINT fk_user_identities_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  refresh_family UUID NOT NULL UNIQUE,
+  user_agent VARCHAR(255) DEFAULT NULL,
+  ip VARCHAR(64) NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  revoked_at TIMESTAMPTZ DEFAULT NULL,
+  CONSTRAINT fk_sessions_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  credential_id VARCHAR(1024) NOT NULL UNIQUE,
+  public_key BYTEA NOT NULL,
+  algorithm INTEGER NOT NULL,
+  sign_count BIGINT NOT NULL DEFAULT 0,
+  last_used_at TIMESTAMPTZ DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CONSTRAINT fk_webauthn_credentials_users FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+-- No foreign keys, the trail outlives the users it mentions
+CREATE TABLE IF NOT EXISTS audit_events (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER DEFAULT NULL,
+  actor_id INTEGER DEFAULT NULL,
+  action VARCHAR(64) NOT NULL,
+  details TEXT DEFAULT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
 INSERT INTO
-  users (username, password)
+  users (username, password, deleted_at)
 VALUES
   (
     'deleteduser',
-    '$2b$12$x3hs5oMgjHdcV1GUEElfsO19JtS6.ixJAX9Cj62GyhpdPAIW25sky'
+    '$2b$12$x3hs5oMgjHdcV1GUEElfsO19JtS6.ixJAX9Cj62GyhpdPAIW25sky',
+    NOW()
   );
 
 INSERT INTO
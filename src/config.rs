@@ -0,0 +1,88 @@
+use std::env;
+
+use crate::utils::{password_hash::PasswordHashing, password_policy::PasswordPolicy, roles::Role};
+
+/// OpenID Connect provider used for single sign-on.
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    /// Discovery is read from `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// Not needed by public clients, which rely on PKCE only.
+    pub client_secret: Option<String>,
+}
+
+/// Directory users log in with, next to the local accounts.
+#[derive(Clone, Debug)]
+pub struct LdapConfig {
+    /// Such as `ldaps://ldap.example.org`.
+    pub url: String,
+    /// DN bound as, `{username}` is replaced by the escaped login.
+    pub dn_template: String,
+    /// Members of a group get its role, others are plain users.
+    pub role_groups: Vec<(String, Role)>,
+}
+
+/// Runtime settings read from the environment, with development defaults.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Public address of the app, used to build the links sent by mail.
+    pub app_url: String,
+    /// Whether the app runs behind a reverse proxy setting `X-Forwarded-For`.
+    pub trust_proxy: bool,
+    /// Algorithm and parameters of new password hashes.
+    pub password_hashing: PasswordHashing,
+    pub password_policy: PasswordPolicy,
+    /// Leading zero bits of the proof of work asked before registering or
+    /// logging in, when the traffic is normal.
+    pub pow_difficulty: u32,
+    /// Days a soft-deleted user can be restored before being purged.
+    pub user_retention_days: i64,
+    /// Single sign-on is only enabled when `OIDC_ISSUER` is set.
+    pub oidc: Option<OidcConfig>,
+    /// LDAP logins are only enabled when `LDAP_URL` is set.
+    pub ldap: Option<LdapConfig>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            app_url: env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string()),
+            trust_proxy: env::var("TRUST_PROXY").is_ok_and(|value| value == "true"),
+            password_hashing: PasswordHashing::from_env(),
+            password_policy: PasswordPolicy::from_env(),
+            pow_difficulty: env::var("POW_DIFFICULTY").map_or(18, |value| {
+                value.parse().expect("POW_DIFFICULTY must be a number")
+            }),
+            user_retention_days: env::var("USER_RETENTION_DAYS").map_or(30, |value| {
+                value.parse().expect("USER_RETENTION_DAYS must be a number")
+            }),
+            oidc: env::var("OIDC_ISSUER").ok().map(|issuer| OidcConfig {
+                issuer,
+                client_id: env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID must be set"),
+                client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            }),
+            ldap: env::var("LDAP_URL").ok().map(|url| LdapConfig {
+                url,
+                dn_template: env::var("LDAP_DN_TEMPLATE").expect("LDAP_DN_TEMPLATE must be set"),
+                role_groups: env::var("LDAP_ROLE_GROUPS").map_or(Vec::new(), |value| {
+                    value
+                        .split(';')
+                        .filter(|mapping| !mapping.trim().is_empty())
+                        .map(|mapping| {
+                            let (role, group) = mapping
+                                .split_once('=')
+                                .expect("LDAP_ROLE_GROUPS entries must be <role>=<group DN>");
+                            let role = role
+                                .trim()
+                                .parse()
+                                .expect("LDAP_ROLE_GROUPS has an unknown role");
+
+                            (group.trim().to_string(), role)
+                        })
+                        .collect()
+                }),
+            }),
+        }
+    }
+}
@@ -2,5 +2,14 @@
 
 pub mod prelude;
 
+pub mod api_keys;
+pub mod audit_events;
+pub mod recovery_codes;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
+pub mod sessions;
 pub mod tasks;
+pub mod user_identities;
+pub mod user_tokens;
 pub mod users;
+pub mod webauthn_credentials;
@@ -1,4 +1,13 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.10.5
 
+pub use super::api_keys::Entity as ApiKeys;
+pub use super::audit_events::Entity as AuditEvents;
+pub use super::recovery_codes::Entity as RecoveryCodes;
+pub use super::refresh_tokens::Entity as RefreshTokens;
+pub use super::revoked_tokens::Entity as RevokedTokens;
+pub use super::sessions::Entity as Sessions;
 pub use super::tasks::Entity as Tasks;
+pub use super::user_identities::Entity as UserIdentities;
+pub use super::user_tokens::Entity as UserTokens;
 pub use super::users::Entity as Users;
+pub use super::webauthn_credentials::Entity as WebauthnCredentials;
@@ -1,4 +1,13 @@
-use axum_webapp::server::run;
+use std::{env, sync::Arc};
+
+use axum_webapp::{
+    config::Config,
+    server::run,
+    utils::{
+        jwt_keys::JwtKeys,
+        mailer::{FileMailer, Mailer, SmtpMailer},
+    },
+};
 use dotenvy::dotenv;
 use dotenvy_macro::dotenv;
 
@@ -9,5 +18,23 @@ async fn main() {
     let database_uri = dotenv!("DATABASE_URL").to_owned();
     let jwt_secret = dotenv!("JWT_SECRET").to_owned();
 
-    run(database_uri, jwt_secret).await;
+    // Asymmetric keys are optional, HS256 with the secret is used without them
+    let jwt_keys = match env::var("JWT_KEYS_DIR") {
+        Ok(keys_dir) => {
+            let active_kid = env::var("JWT_ACTIVE_KID").expect("JWT_ACTIVE_KID must be set");
+            JwtKeys::from_dir(keys_dir, &active_kid).unwrap()
+        }
+        Err(_) => JwtKeys::from_secret(&jwt_secret),
+    };
+
+    // Without SMTP server mails are only written to the `mails` directory
+    let mailer: Arc<dyn Mailer> = match env::var("SMTP_URL") {
+        Ok(smtp_url) => {
+            let mail_from = env::var("MAIL_FROM").expect("MAIL_FROM must be set");
+            Arc::new(SmtpMailer::new(&smtp_url, &mail_from).unwrap())
+        }
+        Err(_) => Arc::new(FileMailer::new("mails")),
+    };
+
+    run(database_uri, jwt_keys, mailer, Config::from_env()).await;
 }
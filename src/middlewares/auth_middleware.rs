@@ -1 +1,258 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri, State},
+    headers::{authorization::Bearer, Authorization},
+    http::{request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+    TypedHeader,
+};
 
+use std::str::FromStr;
+
+use axum_extra::extract::CookieJar;
+use sea_orm::{prelude::Uuid, DatabaseConnection};
+
+use crate::utils::{
+    api_key::{find_api_key, parse_scopes, API_KEY_PREFIX},
+    audit::{record_audit_event, IMPERSONATED_REQUEST},
+    jwt::{decode_token, Claims},
+    jwt_keys::JwtKeys,
+    opaque_token::hash_token,
+    revocation::is_revoked,
+    roles::{Permission, Role},
+    session::touch_session,
+    session_cookie::{csrf_header, SESSION_COOKIE},
+};
+
+/// How the caller of a request proved who they are.
+#[derive(Clone, Debug)]
+pub enum Credential {
+    /// An access token, `jti` and `exp` are kept so it can be revoked.
+    Token {
+        jti: Uuid,
+        exp: usize,
+        sid: Option<i32>,
+    },
+    /// An API key, only granted the permissions of its scopes.
+    ApiKey { id: i32, scopes: Vec<Permission> },
+}
+
+/// The caller of a request that went through `auth_middleware`.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub id: i32,
+    pub is_admin: bool,
+    pub role: Role,
+    pub credential: Credential,
+    /// The admin acting as the user with an impersonation token.
+    pub impersonator: Option<i32>,
+}
+
+impl AuthenticatedUser {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        let in_scope = match &self.credential {
+            Credential::Token { .. } => true,
+            Credential::ApiKey { scopes, .. } => scopes.contains(&permission),
+        };
+
+        in_scope && self.role.has_permission(permission)
+    }
+}
+
+impl From<Claims> for AuthenticatedUser {
+    fn from(claims: Claims) -> Self {
+        AuthenticatedUser {
+            id: claims.id,
+            is_admin: claims.is_admin,
+            role: claims.role,
+            credential: Credential::Token {
+                jti: claims.jti,
+                exp: claims.exp,
+                sid: claims.sid,
+            },
+            impersonator: claims.act.map(|actor| actor.sub),
+        }
+    }
+}
+
+/// Every authentication failure gets the same response so callers can't tell
+/// a missing header from an expired or forged token.
+pub fn unauthorized() -> (StatusCode, String) {
+    (StatusCode::UNAUTHORIZED, "Unauthorized".to_string())
+}
+
+pub fn forbidden() -> (StatusCode, String) {
+    (StatusCode::FORBIDDEN, "Forbidden".to_string())
+}
+
+pub async fn auth_middleware<B>(
+    State(jwt_keys): State<JwtKeys>,
+    State(database_conn): State<DatabaseConnection>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    jar: CookieJar,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, String)> {
+    let user = match bearer {
+        Some(TypedHeader(Authorization(bearer))) if bearer.token().starts_with(API_KEY_PREFIX) => {
+            authenticate_api_key(&database_conn, bearer.token()).await?
+        }
+        Some(TypedHeader(Authorization(bearer))) => {
+            decode_access_token(&jwt_keys, &database_conn, bearer.token())
+                .await?
+                .into()
+        }
+        None => {
+            let cookie = jar.get(SESSION_COOKIE).ok_or_else(unauthorized)?;
+            let claims = decode_access_token(&jwt_keys, &database_conn, cookie.value()).await?;
+
+            // Browsers attach the cookie to cross-site requests too, changes
+            // must prove that the caller could read the CSRF token
+            let csrf_hash = csrf_header(request.headers()).map(hash_token);
+            if !request.method().is_safe() && (claims.csrf.is_none() || claims.csrf != csrf_hash) {
+                return Err(forbidden());
+            }
+
+            claims.into()
+        }
+    };
+
+    // Recorded before the request runs, nothing happens under impersonation
+    // without a trace
+    if let Some(actor_id) = user.impersonator {
+        // Nested routers only see the end of the path
+        let path = match request.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.path(),
+            None => request.uri().path(),
+        };
+        let details = format!("{} {}", request.method(), path);
+
+        record_audit_event(
+            &database_conn,
+            Some(user.id),
+            Some(actor_id),
+            IMPERSONATED_REQUEST,
+            Some(details),
+        )
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+    }
+
+    request.extensions_mut().insert(user);
+
+    Ok(next.run(request).await)
+}
+
+async fn decode_access_token(
+    jwt_keys: &JwtKeys,
+    database_conn: &DatabaseConnection,
+    token: &str,
+) -> Result<Claims, (StatusCode, String)> {
+    let claims = decode_token(jwt_keys, token.to_string())
+        .await
+        .map_err(|_| unauthorized())?;
+
+    if is_revoked(database_conn, &claims)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+    {
+        return Err(unauthorized());
+    }
+
+    if let Some(sid) = claims.sid {
+        if !touch_session(database_conn, sid)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+        {
+            return Err(unauthorized());
+        }
+    }
+
+    Ok(claims)
+}
+
+async fn authenticate_api_key(
+    database_conn: &DatabaseConnection,
+    key: &str,
+) -> Result<AuthenticatedUser, (StatusCode, String)> {
+    let (api_key, user) = find_api_key(database_conn, key)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+        .ok_or_else(unauthorized)?;
+
+    let role =
+        Role::from_str(&user.role).map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+    let scopes = parse_scopes(&api_key.scopes)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(AuthenticatedUser {
+        id: user.id,
+        is_admin: role == Role::Admin,
+        role,
+        credential: Credential::ApiKey {
+            id: api_key.id,
+            scopes,
+        },
+        impersonator: None,
+    })
+}
+
+/// Guard for routes behind `auth_middleware`, the permission to check is
+/// passed as the middleware state.
+pub async fn require_permission<B>(
+    State(permission): State<Permission>,
+    user: AuthenticatedUser,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, String)> {
+    if !user.has_permission(permission) {
+        return Err(forbidden());
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Guard for the account routes, which an API key must not reach whatever its
+/// scopes.
+pub async fn require_token<B>(
+    user: AuthenticatedUser,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, String)> {
+    if let Credential::ApiKey { .. } = user.credential {
+        return Err(forbidden());
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Guard for the administration and account routes, an impersonation token
+/// only gives access to the user's data.
+pub async fn forbid_impersonation<B>(
+    user: AuthenticatedUser,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, String)> {
+    if user.impersonator.is_some() {
+        return Err(forbidden());
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthenticatedUser>()
+            .cloned()
+            .ok_or_else(unauthorized)
+    }
+}
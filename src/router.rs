@@ -1,29 +1,208 @@
-use crate::routes::auth::{auth, renew_auth};
+use crate::middlewares::auth_middleware::{
+    auth_middleware, forbid_impersonation, require_permission, require_token,
+};
+use crate::routes::api_key::{create_api_key, delete_api_key, get_api_keys};
+use crate::routes::auth::{auth, jwks, login_mfa, logout, renew_auth};
+use crate::routes::data_export::{export_me, export_user};
 use crate::routes::index::hello_world;
+use crate::routes::magic_link::{magic_link_login, request_magic_link};
+use crate::routes::mfa::{confirm_totp, disable_totp, enroll_totp};
+use crate::routes::oidc::{oidc_callback, oidc_login};
+use crate::routes::password::{confirm_password_reset, request_password_reset};
+use crate::routes::proof_of_work::get_pow_challenge;
+use crate::routes::session::{delete_session, get_sessions};
 use crate::routes::task::{create_task, delete_task, get_all_tasks, get_task, update_task};
-use crate::routes::user::{create_user, delete_user_by_username, get_all_users};
+use crate::routes::user::{
+    admin_create_user, change_password, change_user_role, create_user, delete_user_by_username,
+    disable_user, enable_user, force_password_reset, get_all_users, get_me, get_user,
+    impersonate_user, restore_user, revoke_user_sessions, update_me,
+};
+use crate::routes::verification::{resend_verification, verify_email};
+use crate::routes::webauthn::{
+    delete_webauthn_credential, finish_webauthn_login, finish_webauthn_registration,
+    get_webauthn_credentials, start_webauthn_login, start_webauthn_registration,
+};
 use crate::server::AppState;
-use axum::routing::{delete, get, post};
+use crate::utils::roles::Permission;
+use axum::middleware;
+use axum::routing::{delete, get, post, put};
 use axum::Router;
 
 pub async fn create_routes(app_state: AppState) -> Router {
     let guest_nest = Router::new()
         .route("/", get(hello_world))
+        .route("/pow/challenge", get(get_pow_challenge))
         .route("/login", post(auth))
+        .route("/login/mfa", post(login_mfa))
         .route("/register", post(create_user))
-        .route("/renew_auth", post(renew_auth));
+        .route("/renew_auth", post(renew_auth))
+        .route("/verify_email", post(verify_email))
+        .route("/verify_email/resend", post(resend_verification))
+        .route("/password_reset", post(request_password_reset))
+        .route("/password_reset/confirm", post(confirm_password_reset))
+        .route("/magic_link", post(request_magic_link))
+        .route("/magic_link/login", post(magic_link_login))
+        .route("/oidc/login", get(oidc_login))
+        .route("/oidc/callback", get(oidc_callback))
+        .route("/webauthn/login/start", post(start_webauthn_login))
+        .route("/webauthn/login/finish", post(finish_webauthn_login))
+        .route("/.well-known/jwks.json", get(jwks));
+
+    let account_nest = Router::new()
+        .route("/logout", post(logout))
+        .route("/mfa/totp/enroll", post(enroll_totp))
+        .route("/mfa/totp/confirm", post(confirm_totp))
+        .route("/mfa/totp/disable", post(disable_totp))
+        .route("/api_keys", post(create_api_key).get(get_api_keys))
+        .route("/api_keys/:id", delete(delete_api_key))
+        .route(
+            "/webauthn/register/start",
+            post(start_webauthn_registration),
+        )
+        .route(
+            "/webauthn/register/finish",
+            post(finish_webauthn_registration),
+        )
+        .route("/webauthn/credentials", get(get_webauthn_credentials))
+        .route(
+            "/webauthn/credentials/:id",
+            delete(delete_webauthn_credential),
+        )
+        .route("/me", get(get_me).patch(update_me))
+        .route("/me/password", post(change_password))
+        .route("/me/export", get(export_me))
+        .route("/me/sessions", get(get_sessions))
+        .route("/me/sessions/:id", delete(delete_session))
+        .route_layer(middleware::from_fn(require_token))
+        .route_layer(middleware::from_fn(forbid_impersonation))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth_middleware,
+        ));
 
     let user_nest = Router::new()
-        .route("/", post(create_task).get(get_all_tasks))
-        .route("/:id", get(get_task).put(update_task).delete(delete_task));
+        .route(
+            "/",
+            get(get_all_tasks)
+                .route_layer(middleware::from_fn_with_state(
+                    Permission::ReadTasks,
+                    require_permission,
+                ))
+                .merge(
+                    post(create_task).route_layer(middleware::from_fn_with_state(
+                        Permission::WriteTasks,
+                        require_permission,
+                    )),
+                ),
+        )
+        .route(
+            "/:id",
+            get(get_task)
+                .route_layer(middleware::from_fn_with_state(
+                    Permission::ReadTasks,
+                    require_permission,
+                ))
+                .merge(put(update_task).delete(delete_task).route_layer(
+                    middleware::from_fn_with_state(Permission::WriteTasks, require_permission),
+                )),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth_middleware,
+        ));
 
     let admin_nest = Router::new()
-        .route("/", get(get_all_users))
-        .route("/:username", delete(delete_user_by_username));
+        .route(
+            "/",
+            get(get_all_users)
+                .route_layer(middleware::from_fn_with_state(
+                    Permission::ReadUsers,
+                    require_permission,
+                ))
+                .merge(
+                    post(admin_create_user).route_layer(middleware::from_fn_with_state(
+                        Permission::WriteUsers,
+                        require_permission,
+                    )),
+                ),
+        )
+        .route(
+            "/:username",
+            get(get_user)
+                .route_layer(middleware::from_fn_with_state(
+                    Permission::ReadUsers,
+                    require_permission,
+                ))
+                .merge(delete(delete_user_by_username).route_layer(
+                    middleware::from_fn_with_state(Permission::WriteUsers, require_permission),
+                )),
+        )
+        .route(
+            "/:username/restore",
+            post(restore_user).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/export",
+            get(export_user).route_layer(middleware::from_fn_with_state(
+                Permission::ReadUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/disable",
+            post(disable_user).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/enable",
+            post(enable_user).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/password_reset",
+            post(force_password_reset).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/role",
+            put(change_user_role).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/revoke_sessions",
+            post(revoke_user_sessions).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route(
+            "/:username/impersonate",
+            post(impersonate_user).route_layer(middleware::from_fn_with_state(
+                Permission::WriteUsers,
+                require_permission,
+            )),
+        )
+        .route_layer(middleware::from_fn(forbid_impersonation))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth_middleware,
+        ));
 
     Router::new()
         .nest("", guest_nest)
+        .merge(account_nest)
         .nest("/task", user_nest)
         .nest("/user", admin_nest)
         .with_state(app_state)
-}
\ No newline at end of file
+}
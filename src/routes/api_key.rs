@@ -0,0 +1,145 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    prelude::DateTimeWithTimeZone, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    database::api_keys,
+    middlewares::auth_middleware::AuthenticatedUser,
+    utils::{
+        api_key::{format_scopes, generate_api_key, parse_scopes},
+        opaque_token::hash_token,
+        roles::Permission,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ApiKeyRequest {
+    #[validate(length(min = 1, max = 64, message = "must have between 1 and 64 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, message = "must have at least 1 scope"))]
+    pub scopes: Vec<Permission>,
+    #[validate(range(min = 1, max = 365, message = "must be between 1 and 365 days"))]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    pub expires_at: Option<DateTimeWithTimeZone>,
+    pub last_used_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+/// The key itself is only returned once, at creation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+impl TryFrom<api_keys::Model> for ApiKeyResponse {
+    type Error = String;
+
+    fn try_from(api_key: api_keys::Model) -> Result<Self, Self::Error> {
+        Ok(ApiKeyResponse {
+            id: api_key.id,
+            name: api_key.name,
+            scopes: parse_scopes(&api_key.scopes)?,
+            expires_at: api_key.expires_at,
+            last_used_at: api_key.last_used_at,
+            created_at: api_key.created_at,
+        })
+    }
+}
+
+/// A key can only be given scopes its owner's role grants.
+pub async fn create_api_key(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    Json(user_request): Json<ApiKeyRequest>,
+) -> Result<Json<CreatedApiKeyResponse>, (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    if let Some(scope) = user_request
+        .scopes
+        .iter()
+        .find(|scope| !user.role.has_permission(**scope))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Scope not allowed: {}", scope.as_str()),
+        ));
+    }
+
+    let key = generate_api_key();
+
+    let api_key = api_keys::ActiveModel {
+        user_id: Set(user.id),
+        name: Set(user_request.name),
+        key_hash: Set(hash_token(&key)),
+        scopes: Set(format_scopes(&user_request.scopes)),
+        expires_at: Set(user_request
+            .expires_in_days
+            .map(|days| (Utc::now() + Duration::days(days)).into())),
+        ..Default::default()
+    }
+    .insert(&database_conn)
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let api_key = ApiKeyResponse::try_from(api_key)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(Json(CreatedApiKeyResponse { key, api_key }))
+}
+
+pub async fn get_api_keys(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<Vec<ApiKeyResponse>>, (StatusCode, String)> {
+    let api_keys = api_keys::Entity::find()
+        .filter(api_keys::Column::UserId.eq(user.id))
+        .order_by_asc(api_keys::Column::Id)
+        .all(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .into_iter()
+        .map(ApiKeyResponse::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(Json(api_keys))
+}
+
+pub async fn delete_api_key(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    let result = api_keys::Entity::delete_many()
+        .filter(api_keys::Column::Id.eq(id))
+        .filter(api_keys::Column::UserId.eq(user.id))
+        .exec(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if result.rows_affected == 0 {
+        return Err((StatusCode::NOT_FOUND, "API key not found".to_string()));
+    }
+
+    Ok(())
+}
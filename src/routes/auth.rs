@@ -1,25 +1,71 @@
-use axum::{extract::State, http::StatusCode, Json};
-use bcrypt::verify;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::Duration;
+use jsonwebtoken::jwk::JwkSet;
+use sea_orm::{prelude::Uuid, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
 use crate::{
-    database::users,
-    utils::jwt::{create_token, refresh_token},
+    database::{refresh_tokens, sessions, users},
+    middlewares::auth_middleware::{forbidden, unauthorized, AuthenticatedUser, Credential},
+    routes::{mfa::verify_second_factor, proof_of_work::check_pow_solution},
+    utils::{
+        authenticator::Authenticator,
+        jwt::{create_challenge_token, create_cookie_token, create_token, decode_challenge_token},
+        jwt_keys::JwtKeys,
+        login_throttle::LoginThrottle,
+        opaque_token::{generate_token, hash_token, TOKEN_LENGTH},
+        proof_of_work::{PowSolution, ProofOfWork},
+        refresh_token::{issue_refresh_token, revoke_family, rotate_refresh_token},
+        revocation::revoke_token,
+        roles::Role,
+        session::{
+            create_session, find_session_by_family, revoke_session, touch_session, ClientInfo,
+        },
+        session_cookie::{
+            csrf_header, remove_session_cookies, set_session_cookies, SessionMode, SessionParams,
+            CSRF_COOKIE, REFRESH_COOKIE,
+        },
+    },
 };
 
+const MFA_AUDIENCE: &str = "mfa";
+const MFA_CHALLENGE_MINUTES: i64 = 5;
+
+/// Same error for unknown users and wrong passwords.
+fn invalid_credentials() -> (StatusCode, String) {
+    (
+        StatusCode::UNAUTHORIZED,
+        "Invalid username or password".to_string(),
+    )
+}
+
+pub fn too_many_attempts() -> (StatusCode, String) {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too many failed attempts, try again later".to_string(),
+    )
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct AuthRequest {
     #[validate(email(message = "must be a valid email"))]
     pub username: String,
-    #[validate(length(min = 8, message = "must have at least 8 characters"))]
     pub password: String,
+    pub pow: PowSolution,
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct AuthResponse {
     pub token: String,
+    pub refresh_token: String,
 }
 
 pub struct User {
@@ -27,57 +73,420 @@ pub struct User {
     pub username: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MfaChallengeResponse {
+    pub mfa_token: String,
+}
+
+/// In cookie mode the tokens are only set as HttpOnly cookies, the body
+/// carries the CSRF token to send back in the `X-CSRF-Token` header.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CookieSessionResponse {
+    pub csrf_token: String,
+}
+
+/// Users with two-factor authentication get a challenge to exchange at
+/// `/login/mfa` instead of their tokens.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    CookieSession(CookieSessionResponse),
+    MfaRequired(MfaChallengeResponse),
+}
+
+/// Records a session with a new refresh token family, returns the session id
+/// and the first refresh token.
+async fn open_session(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    client: &ClientInfo,
+) -> Result<(i32, String), (StatusCode, String)> {
+    let family = Uuid::new_v4();
+
+    let sid = create_session(database_conn, user_id, family, client)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let refresh_token = issue_refresh_token(database_conn, user_id, family)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok((sid, refresh_token))
+}
+
+/// Issues the access token and a new refresh token family for the user.
+pub async fn issue_auth_response(
+    database_conn: &DatabaseConnection,
+    jwt_keys: &JwtKeys,
+    user: &users::Model,
+    client: &ClientInfo,
+) -> Result<AuthResponse, (StatusCode, String)> {
+    let role =
+        Role::from_str(&user.role).map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let (sid, refresh_token) = open_session(database_conn, user.id, client).await?;
+
+    let token = create_token(jwt_keys, user.id, role, Some(sid))
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(AuthResponse {
+        token,
+        refresh_token,
+    })
+}
+
+/// Sets the cookies of a cookie session, with a new CSRF token.
+async fn issue_cookie_session(
+    jwt_keys: &JwtKeys,
+    user_id: i32,
+    role: Role,
+    sid: Option<i32>,
+    refresh_token: String,
+    jar: CookieJar,
+) -> Result<(CookieJar, CookieSessionResponse), (StatusCode, String)> {
+    let csrf_token = generate_token();
+
+    let token = create_cookie_token(jwt_keys, user_id, role, sid, &csrf_token)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let jar = set_session_cookies(jar, token, refresh_token, csrf_token.clone());
+
+    Ok((jar, CookieSessionResponse { csrf_token }))
+}
+
+/// Issues the tokens of a new session the way the client asked for.
+pub async fn start_session(
+    database_conn: &DatabaseConnection,
+    jwt_keys: &JwtKeys,
+    user: &users::Model,
+    client: &ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
+) -> Result<(CookieJar, LoginResponse), (StatusCode, String)> {
+    // Whichever way they logged in, deleted accounts get no session
+    if user.deleted_at.is_some() {
+        return Err(unauthorized());
+    }
+
+    if user.disabled_at.is_some() {
+        return Err((StatusCode::FORBIDDEN, "Account is disabled".to_string()));
+    }
+
+    if mode == SessionMode::Bearer {
+        let response = issue_auth_response(database_conn, jwt_keys, user, client).await?;
+
+        return Ok((jar, LoginResponse::Authenticated(response)));
+    }
+
+    let role =
+        Role::from_str(&user.role).map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let (sid, refresh_token) = open_session(database_conn, user.id, client).await?;
+
+    let (jar, response) =
+        issue_cookie_session(jwt_keys, user.id, role, Some(sid), refresh_token, jar).await?;
+
+    Ok((jar, LoginResponse::CookieSession(response)))
+}
+
+/// Last step of every way to log in: users with two-factor authentication get
+/// a challenge, the others their session.
+pub async fn finish_login(
+    database_conn: &DatabaseConnection,
+    jwt_keys: &JwtKeys,
+    user: &users::Model,
+    client: &ClientInfo,
+    mode: SessionMode,
+    jar: CookieJar,
+) -> Result<(CookieJar, LoginResponse), (StatusCode, String)> {
+    if user.totp_enabled_at.is_some() {
+        let mfa_token = create_challenge_token(
+            jwt_keys,
+            user.id,
+            MFA_AUDIENCE,
+            Duration::minutes(MFA_CHALLENGE_MINUTES),
+        )
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+        return Ok((
+            jar,
+            LoginResponse::MfaRequired(MfaChallengeResponse { mfa_token }),
+        ));
+    }
+
+    start_session(database_conn, jwt_keys, user, client, mode, jar).await
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn auth(
-    State(jwt_secret): State<String>,
+    State(jwt_keys): State<JwtKeys>,
     State(database_conn): State<DatabaseConnection>,
+    State(login_throttle): State<LoginThrottle>,
+    State(authenticator): State<Arc<dyn Authenticator>>,
+    State(proof_of_work): State<ProofOfWork>,
+    client: ClientInfo,
+    Query(params): Query<SessionParams>,
+    jar: CookieJar,
     Json(user_request): Json<AuthRequest>,
-) -> Result<Json<AuthResponse>, (StatusCode, String)> {
+) -> Result<(CookieJar, Json<LoginResponse>), (StatusCode, String)> {
     if let Err(errors) = user_request.validate() {
         return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
     }
 
-    let user = users::Entity::find()
-        .filter(users::Column::Username.eq(user_request.username.clone()))
+    check_pow_solution(&jwt_keys, &proof_of_work, &user_request.pow).await?;
+
+    if login_throttle
+        .locked_for(&user_request.username, &client.ip)
+        .is_some()
+    {
+        return Err(too_many_attempts());
+    }
+
+    let user = authenticator
+        .authenticate(
+            &database_conn,
+            &user_request.username,
+            &user_request.password,
+        )
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let user = match user {
+        Some(user) => user,
+        None => {
+            login_throttle.record_failure(&user_request.username, &client.ip);
+            return Err(invalid_credentials());
+        }
+    };
+
+    login_throttle.record_success(&user_request.username);
+
+    if user.verified_at.is_none() {
+        return Err((StatusCode::FORBIDDEN, "Email not verified".to_string()));
+    }
+
+    let (jar, response) = finish_login(
+        &database_conn,
+        &jwt_keys,
+        &user,
+        &client,
+        params.session,
+        jar,
+    )
+    .await?;
+
+    Ok((jar, Json(response)))
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    #[validate(length(min = 6, max = 11, message = "Code is not valid"))]
+    pub code: String,
+}
+
+/// Second login step, accepts a TOTP code or an unused recovery code.
+pub async fn login_mfa(
+    State(jwt_keys): State<JwtKeys>,
+    State(database_conn): State<DatabaseConnection>,
+    State(login_throttle): State<LoginThrottle>,
+    client: ClientInfo,
+    Query(params): Query<SessionParams>,
+    jar: CookieJar,
+    Json(user_request): Json<MfaLoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let claims = decode_challenge_token(&jwt_keys, user_request.mfa_token, MFA_AUDIENCE)
+        .await
+        .map_err(|_| unauthorized())?;
+
+    let user = users::Entity::find_by_id(claims.id)
+        .filter(users::Column::DeletedAt.is_null())
         .one(&database_conn)
         .await
-        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or_else(unauthorized)?;
+
+    if login_throttle
+        .locked_for(&user.username, &client.ip)
+        .is_some()
+    {
+        return Err(too_many_attempts());
+    }
+
+    if !verify_second_factor(&database_conn, &user, &user_request.code).await? {
+        login_throttle.record_failure(&user.username, &client.ip);
+        return Err((StatusCode::UNAUTHORIZED, "Invalid code".to_string()));
+    }
 
-    match user {
-        Some(user) => {
-            let is_valid = verify(&user_request.password, &user.password)
-                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    login_throttle.record_success(&user.username);
 
-            if !is_valid {
-                return Err((StatusCode::UNAUTHORIZED, "Invalid password".to_string()));
+    let (jar, response) = start_session(
+        &database_conn,
+        &jwt_keys,
+        &user,
+        &client,
+        params.session,
+        jar,
+    )
+    .await?;
+
+    Ok((jar, Json(response)))
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct RenewRequest {
+    #[validate(length(equal = "TOKEN_LENGTH", message = "Token is not valid"))]
+    pub refresh_token: String,
+}
+
+/// Without a body the refresh token is read from the cookie of a cookie
+/// session, which then needs the CSRF token like any change.
+pub async fn renew_auth(
+    State(jwt_keys): State<JwtKeys>,
+    State(database_conn): State<DatabaseConnection>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    user_request: Option<Json<RenewRequest>>,
+) -> Result<(CookieJar, Json<LoginResponse>), (StatusCode, String)> {
+    let (mode, presented_token) = match user_request {
+        Some(Json(user_request)) => {
+            if let Err(errors) = user_request.validate() {
+                return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+            }
+
+            (SessionMode::Bearer, user_request.refresh_token)
+        }
+        None => {
+            let refresh_cookie = jar.get(REFRESH_COOKIE).ok_or_else(unauthorized)?;
+            let csrf_cookie = jar.get(CSRF_COOKIE).ok_or_else(forbidden)?;
+            if csrf_header(&headers) != Some(csrf_cookie.value()) {
+                return Err(forbidden());
             }
 
-            let token = create_token(jwt_secret, user.id)
+            (SessionMode::Cookie, refresh_cookie.value().to_string())
+        }
+    };
+
+    let (stored, refresh_token) = rotate_refresh_token(&database_conn, &presented_token).await?;
+
+    let session = find_session_by_family(&database_conn, stored.family)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+    let sid = match session {
+        Some(session) => {
+            if !touch_session(&database_conn, session.id)
                 .await
-                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+            {
+                return Err(unauthorized());
+            }
 
-            return Ok(Json(AuthResponse { token }));
+            Some(session.id)
         }
-        None => return Err((StatusCode::NOT_FOUND, "User not found".to_string())),
+        None => None,
+    };
+
+    // The role is read again so a promotion or demotion applies on renewal
+    let user = users::Entity::find_by_id(stored.user_id)
+        .filter(users::Column::DeletedAt.is_null())
+        .filter(users::Column::DisabledAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or_else(unauthorized)?;
+
+    let role =
+        Role::from_str(&user.role).map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    if mode == SessionMode::Cookie {
+        let (jar, response) =
+            issue_cookie_session(&jwt_keys, user.id, role, sid, refresh_token, jar).await?;
+
+        return Ok((jar, Json(LoginResponse::CookieSession(response))));
     }
+
+    let token = create_token(&jwt_keys, user.id, role, sid)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok((
+        jar,
+        Json(LoginResponse::Authenticated(AuthResponse {
+            token,
+            refresh_token,
+        })),
+    ))
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
-pub struct RenewRequest {
-    #[validate(length(min = 160, max = 160, message = "Token is not valid"))]
-    pub token: String,
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
 }
 
-pub async fn renew_auth(
-    State(jwt_secret): State<String>,
-    Json(user_request): Json<RenewRequest>,
-) -> Result<Json<AuthResponse>, (StatusCode, String)> {
-    if let Err(errors) = user_request.validate() {
-        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
-    }
+/// Revokes the access token used for the call and ends its session, and the
+/// refresh token family when the matching refresh token is given or in the
+/// session cookies, which are cleared.
+pub async fn logout(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    jar: CookieJar,
+    user_request: Option<Json<LogoutRequest>>,
+) -> Result<CookieJar, (StatusCode, String)> {
+    let Credential::Token { jti, exp, sid } = user.credential else {
+        return Err(forbidden());
+    };
 
-    let token = refresh_token(jwt_secret, user_request.token)
+    revoke_token(&database_conn, user.id, jti, exp)
         .await
-        .map_err(|errors| (StatusCode::UNAUTHORIZED, format!("{}", errors)))?;
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    if let Some(sid) = sid {
+        let session = sessions::Entity::find_by_id(sid)
+            .one(&database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+        if let Some(session) = session {
+            revoke_session(&database_conn, session)
+                .await
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+        }
+    }
+
+    let refresh_token = user_request
+        .and_then(|Json(request)| request.refresh_token)
+        .or_else(|| {
+            jar.get(REFRESH_COOKIE)
+                .map(|cookie| cookie.value().to_string())
+        });
+
+    if let Some(refresh_token) = refresh_token {
+        let stored = refresh_tokens::Entity::find()
+            .filter(refresh_tokens::Column::TokenHash.eq(hash_token(&refresh_token)))
+            .filter(refresh_tokens::Column::UserId.eq(user.id))
+            .one(&database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+        if let Some(stored) = stored {
+            revoke_family(&database_conn, stored.family)
+                .await
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+        }
+    }
+
+    Ok(remove_session_cookies(jar))
+}
 
-    Ok(Json(AuthResponse { token }))
+/// Public keys for other services to verify our tokens, empty with HS256.
+pub async fn jwks(State(jwt_keys): State<JwtKeys>) -> Json<JwkSet> {
+    Json(jwt_keys.jwks())
 }
@@ -0,0 +1,93 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::Response,
+};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    database::users,
+    middlewares::auth_middleware::AuthenticatedUser,
+    routes::user::DeleteUserByUsernameRequest,
+    utils::{
+        audit::{record_audit_event, DATA_EXPORTED},
+        data_export::{spawn_data_export, ExportFormat},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DataExportParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Records the export and starts streaming it as a download.
+async fn export_user_data(
+    database_conn: DatabaseConnection,
+    user: users::Model,
+    actor_id: Option<i32>,
+    format: ExportFormat,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    record_audit_event(
+        &database_conn,
+        Some(user.id),
+        actor_id,
+        DATA_EXPORTED,
+        Some(format.extension().to_string()),
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let filename = format!("export-{}.{}", user.id, format.extension());
+    let (sender, body) = Body::channel();
+    spawn_data_export(database_conn, user, format, sender);
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(body)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))
+}
+
+/// Everything stored about the caller, to answer a data access request.
+pub async fn export_me(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    Query(params): Query<DataExportParams>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let user = users::Entity::find_by_id(user.id)
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    export_user_data(database_conn, user, None, params.format).await
+}
+
+/// Same export on behalf of a user, deleted ones included until they are
+/// purged.
+pub async fn export_user(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+    Query(params): Query<DataExportParams>,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    if let Err(errors) = username.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(username.username))
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    export_user_data(database_conn, user, Some(admin.id), params.format).await
+}
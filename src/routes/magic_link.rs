@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    config::Config,
+    database::{user_tokens, users},
+    routes::auth::{finish_login, LoginResponse},
+    utils::{
+        jwt_keys::JwtKeys,
+        mailer::{Mail, Mailer},
+        opaque_token::TOKEN_LENGTH,
+        session::ClientInfo,
+        session_cookie::SessionParams,
+        user_token::{consume_user_token, discard_user_tokens, issue_user_token, TokenPurpose},
+    },
+};
+
+const MAGIC_LINK_MINUTES: i64 = 15;
+/// Minimum delay between two login links to the same address
+const MAGIC_LINK_COOLDOWN_SECONDS: i64 = 60;
+/// Maximum number of login links to the same address per hour
+const MAGIC_LINK_HOURLY_LIMIT: u64 = 5;
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MagicLinkRequest {
+    #[validate(email(message = "must be a valid email"))]
+    pub username: String,
+}
+
+/// Mails a login link. Always succeeds so the response doesn't tell whether
+/// the account exists, links past the rate limit are silently not sent.
+pub async fn request_magic_link(
+    State(database_conn): State<DatabaseConnection>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    Json(user_request): Json<MagicLinkRequest>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(user_request.username))
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let now = Utc::now();
+    let recent_links = |since: chrono::DateTime<Utc>| {
+        user_tokens::Entity::find()
+            .filter(user_tokens::Column::UserId.eq(user.id))
+            .filter(user_tokens::Column::Purpose.eq(TokenPurpose::MagicLink.as_str()))
+            .filter(user_tokens::Column::CreatedAt.gt(since))
+            .count(&database_conn)
+    };
+
+    let last_minute = recent_links(now - Duration::seconds(MAGIC_LINK_COOLDOWN_SECONDS))
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    let last_hour = recent_links(now - Duration::hours(1))
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if last_minute > 0 || last_hour >= MAGIC_LINK_HOURLY_LIMIT {
+        return Ok(());
+    }
+
+    let token = issue_user_token(
+        &database_conn,
+        user.id,
+        TokenPurpose::MagicLink,
+        Duration::minutes(MAGIC_LINK_MINUTES),
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let mail = Mail {
+        to: user.username,
+        subject: "Your login link".to_string(),
+        body: format!(
+            "Follow this link to log in:\n{}/magic_link?token={}\n\nIt expires in {} minutes and works once. If you didn't ask for it, you can ignore this mail.",
+            config.app_url, token, MAGIC_LINK_MINUTES
+        ),
+    };
+
+    if let Err(errors) = mailer.send(mail).await {
+        eprintln!("Failed to send login link mail: {}", errors);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MagicLinkLoginRequest {
+    #[validate(length(equal = "TOKEN_LENGTH", message = "Token is not valid"))]
+    pub token: String,
+}
+
+/// Logs in with the token of a login link. The link can only be used once,
+/// and using it discards the other links sent to the user.
+pub async fn magic_link_login(
+    State(jwt_keys): State<JwtKeys>,
+    State(database_conn): State<DatabaseConnection>,
+    client: ClientInfo,
+    Query(params): Query<SessionParams>,
+    jar: CookieJar,
+    Json(user_request): Json<MagicLinkLoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user_id = consume_user_token(&database_conn, &user_request.token, TokenPurpose::MagicLink)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+        .ok_or((
+            StatusCode::UNAUTHORIZED,
+            "Link is not valid or expired".to_string(),
+        ))?;
+
+    discard_user_tokens(&database_conn, user_id, TokenPurpose::MagicLink)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let user = users::Entity::find_by_id(user_id)
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((
+            StatusCode::UNAUTHORIZED,
+            "Link is not valid or expired".to_string(),
+        ))?;
+
+    // Receiving the link proves the ownership of the email
+    let user = if user.verified_at.is_none() {
+        let mut user: users::ActiveModel = user.into();
+        user.verified_at = Set(Some(Utc::now().into()));
+        user.update(&database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+    } else {
+        user
+    };
+
+    let (jar, response) = finish_login(
+        &database_conn,
+        &jwt_keys,
+        &user,
+        &client,
+        params.session,
+        jar,
+    )
+    .await?;
+
+    Ok((jar, Json(response)))
+}
@@ -0,0 +1,222 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait,
+    QueryFilter, Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    database::{recovery_codes, users},
+    middlewares::auth_middleware::AuthenticatedUser,
+    utils::{
+        opaque_token::hash_token,
+        totp::{generate_recovery_codes, generate_secret, otpauth_uri, verify_code},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct TotpEnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct TotpCodeRequest {
+    #[validate(length(min = 6, max = 11, message = "Code is not valid"))]
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+async fn find_user(
+    database_conn: &DatabaseConnection,
+    id: i32,
+) -> Result<users::Model, (StatusCode, String)> {
+    users::Entity::find_by_id(id)
+        .one(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))
+}
+
+/// Checks a TOTP code, or burns a recovery code, of a user with two-factor
+/// authentication enabled.
+pub async fn verify_second_factor(
+    database_conn: &DatabaseConnection,
+    user: &users::Model,
+    code: &str,
+) -> Result<bool, (StatusCode, String)> {
+    let secret = match (&user.totp_secret, user.totp_enabled_at) {
+        (Some(secret), Some(_)) => secret,
+        _ => return Ok(false),
+    };
+
+    if code.len() == 6 && code.chars().all(|character| character.is_ascii_digit()) {
+        let step = match verify_code(secret, &user.username, code, user.totp_last_step)
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+        {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+
+        // Conditional so the same code can't be used by two concurrent logins
+        let result = users::Entity::update_many()
+            .col_expr(users::Column::TotpLastStep, Expr::value(step))
+            .filter(users::Column::Id.eq(user.id))
+            .filter(
+                Condition::any()
+                    .add(users::Column::TotpLastStep.is_null())
+                    .add(users::Column::TotpLastStep.lt(step)),
+            )
+            .exec(database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+        return Ok(result.rows_affected == 1);
+    }
+
+    let result = recovery_codes::Entity::update_many()
+        .col_expr(recovery_codes::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(recovery_codes::Column::UserId.eq(user.id))
+        .filter(recovery_codes::Column::CodeHash.eq(hash_token(&code.trim().to_lowercase())))
+        .filter(recovery_codes::Column::UsedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(result.rows_affected > 0)
+}
+
+/// Starts the enrollment, two-factor authentication is only enabled once a
+/// code generated from the secret is confirmed.
+pub async fn enroll_totp(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<TotpEnrollResponse>, (StatusCode, String)> {
+    let user = find_user(&database_conn, user.id).await?;
+
+    if user.totp_enabled_at.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let secret = generate_secret();
+    let otpauth_uri = otpauth_uri(&secret, &user.username)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let mut user: users::ActiveModel = user.into();
+    user.totp_secret = Set(Some(secret.clone()));
+    user.totp_last_step = Set(None);
+    user.update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(Json(TotpEnrollResponse {
+        secret,
+        otpauth_uri,
+    }))
+}
+
+/// Enables two-factor authentication and returns the recovery codes, they
+/// won't be shown again.
+pub async fn confirm_totp(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    Json(user_request): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = find_user(&database_conn, user.id).await?;
+
+    let secret = match (&user.totp_secret, user.totp_enabled_at) {
+        (Some(secret), None) => secret.clone(),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "No two-factor enrollment in progress".to_string(),
+            ))
+        }
+    };
+
+    let step = verify_code(&secret, &user.username, &user_request.code, None)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid code".to_string()))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.totp_enabled_at = Set(Some(Utc::now().into()));
+    user.totp_last_step = Set(Some(step));
+    user.update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    recovery_codes::Entity::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .exec(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let codes = generate_recovery_codes();
+    recovery_codes::Entity::insert_many(codes.iter().map(|code| recovery_codes::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(hash_token(code)),
+        ..Default::default()
+    }))
+    .exec(&database_conn)
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: codes,
+    }))
+}
+
+/// Requires a valid code so a stolen session can't turn the protection off.
+pub async fn disable_totp(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    Json(user_request): Json<TotpCodeRequest>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = find_user(&database_conn, user.id).await?;
+
+    if user.totp_enabled_at.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+
+    if !verify_second_factor(&database_conn, &user, &user_request.code).await? {
+        return Err((StatusCode::BAD_REQUEST, "Invalid code".to_string()));
+    }
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.totp_secret = Set(None);
+    user.totp_enabled_at = Set(None);
+    user.totp_last_step = Set(None);
+    user.update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    recovery_codes::Entity::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .exec(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(())
+}
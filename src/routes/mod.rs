@@ -1,4 +1,14 @@
+pub mod api_key;
 pub mod auth;
+pub mod data_export;
 pub mod index;
+pub mod magic_link;
+pub mod mfa;
+pub mod oidc;
+pub mod password;
+pub mod proof_of_work;
+pub mod session;
 pub mod task;
 pub mod user;
+pub mod verification;
+pub mod webauthn;
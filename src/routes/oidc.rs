@@ -0,0 +1,187 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Redirect,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    database::{user_identities, users},
+    middlewares::auth_middleware::unauthorized,
+    routes::auth::{finish_login, LoginResponse},
+    utils::{
+        jwt_keys::JwtKeys,
+        oidc::{IdTokenClaims, OidcClient},
+        opaque_token::generate_token,
+        password_hash::PasswordHashing,
+        revocation::revoke_all_tokens,
+        session::ClientInfo,
+        session_cookie::SessionMode,
+        user_token::{discard_user_tokens, TokenPurpose},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcCallbackParams {
+    pub code: String,
+    pub state: String,
+}
+
+fn oidc_client(oidc: Option<OidcClient>) -> Result<OidcClient, (StatusCode, String)> {
+    oidc.ok_or((
+        StatusCode::NOT_FOUND,
+        "Single sign-on is not configured".to_string(),
+    ))
+}
+
+/// Sends the user to the identity provider, which redirects back to
+/// `/oidc/callback`.
+pub async fn oidc_login(
+    State(oidc): State<Option<OidcClient>>,
+) -> Result<Redirect, (StatusCode, String)> {
+    let authorization_url = oidc_client(oidc)?
+        .authorization_url()
+        .await
+        .map_err(|errors| (StatusCode::BAD_GATEWAY, errors))?;
+
+    Ok(Redirect::to(&authorization_url))
+}
+
+pub async fn oidc_callback(
+    State(oidc): State<Option<OidcClient>>,
+    State(jwt_keys): State<JwtKeys>,
+    State(database_conn): State<DatabaseConnection>,
+    State(config): State<Config>,
+    client: ClientInfo,
+    Query(params): Query<OidcCallbackParams>,
+) -> Result<Json<LoginResponse>, (StatusCode, String)> {
+    let oidc = oidc_client(oidc)?;
+
+    let claims = oidc.exchange_code(&params.code, &params.state).await?;
+    let user = find_or_provision_user(
+        &database_conn,
+        &config.password_hashing,
+        oidc.issuer(),
+        claims,
+    )
+    .await?;
+
+    let (_, response) = finish_login(
+        &database_conn,
+        &jwt_keys,
+        &user,
+        &client,
+        SessionMode::Bearer,
+        CookieJar::new(),
+    )
+    .await?;
+
+    Ok(Json(response))
+}
+
+/// Finds the user linked to the identity. Otherwise the identity is linked to
+/// the account of its verified email, which is created if needed.
+async fn find_or_provision_user(
+    database_conn: &DatabaseConnection,
+    password_hashing: &PasswordHashing,
+    issuer: &str,
+    claims: IdTokenClaims,
+) -> Result<users::Model, (StatusCode, String)> {
+    let identity = user_identities::Entity::find()
+        .filter(user_identities::Column::Issuer.eq(issuer))
+        .filter(user_identities::Column::Subject.eq(claims.sub.clone()))
+        .find_also_related(users::Entity)
+        .one(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if let Some((_, Some(user))) = identity {
+        return Ok(user);
+    }
+
+    let email = match claims.email {
+        Some(email) if claims.email_verified => email,
+        _ => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "The identity provider didn't return a verified email".to_string(),
+            ))
+        }
+    };
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(email.clone()))
+        .one(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user = match user {
+        Some(user) if user.deleted_at.is_some() => return Err(unauthorized()),
+        Some(user) if user.verified_at.is_some() => user,
+        Some(user) => {
+            // The provider proved the ownership of the email. Whoever
+            // registered it unverified may not be its owner, so the password
+            // and pending links they could know are dropped.
+            let password = password_hashing
+                .hash(&generate_token())
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+            let user_id = user.id;
+            let mut user: users::ActiveModel = user.into();
+            user.password = Set(password);
+            user.verified_at = Set(Some(Utc::now().into()));
+            let user = user
+                .update(database_conn)
+                .await
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+            for purpose in [
+                TokenPurpose::PasswordReset,
+                TokenPurpose::EmailVerification,
+                TokenPurpose::MagicLink,
+            ] {
+                discard_user_tokens(database_conn, user_id, purpose)
+                    .await
+                    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+            }
+            revoke_all_tokens(database_conn, user_id)
+                .await
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+            user
+        }
+        None => {
+            // Nobody knows this password, a local one can be set with a reset
+            let password = password_hashing
+                .hash(&generate_token())
+                .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+            users::ActiveModel {
+                username: Set(email),
+                password: Set(password),
+                verified_at: Set(Some(Utc::now().into())),
+                ..Default::default()
+            }
+            .insert(database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        }
+    };
+
+    user_identities::ActiveModel {
+        user_id: Set(user.id),
+        issuer: Set(issuer.to_string()),
+        subject: Set(claims.sub),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(user)
+}
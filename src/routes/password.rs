@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Duration;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    config::Config,
+    database::users,
+    utils::{
+        mailer::{Mail, Mailer},
+        opaque_token::TOKEN_LENGTH,
+        revocation::revoke_all_tokens,
+        user_token::{
+            consume_user_token, discard_user_tokens, find_user_token, issue_user_token,
+            TokenPurpose,
+        },
+    },
+};
+
+const RESET_TOKEN_HOURS: i64 = 1;
+
+pub async fn send_password_reset_mail(
+    database_conn: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    config: &Config,
+    user: &users::Model,
+) -> Result<(), String> {
+    let token = issue_user_token(
+        database_conn,
+        user.id,
+        TokenPurpose::PasswordReset,
+        Duration::hours(RESET_TOKEN_HOURS),
+    )
+    .await?;
+
+    mailer
+        .send(Mail {
+            to: user.username.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Follow this link to choose a new password:\n{}/reset_password?token={}\n\nIt expires in {} hour.",
+                config.app_url, token, RESET_TOKEN_HOURS
+            ),
+        })
+        .await
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct PasswordResetRequest {
+    #[validate(email(message = "must be a valid email"))]
+    pub username: String,
+}
+
+/// Always succeeds so the response doesn't tell whether the account exists.
+pub async fn request_password_reset(
+    State(database_conn): State<DatabaseConnection>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    Json(user_request): Json<PasswordResetRequest>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(user_request.username))
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if let Some(user) = user {
+        if let Err(errors) =
+            send_password_reset_mail(&database_conn, mailer.as_ref(), &config, &user).await
+        {
+            eprintln!("Failed to send password reset mail: {}", errors);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ConfirmPasswordResetRequest {
+    #[validate(length(equal = "TOKEN_LENGTH", message = "Token is not valid"))]
+    pub token: String,
+    /// Checked against `config.password_policy`.
+    pub password: String,
+}
+
+/// Sets the new password and logs the user out everywhere.
+pub async fn confirm_password_reset(
+    State(database_conn): State<DatabaseConnection>,
+    State(config): State<Config>,
+    Json(user_request): Json<ConfirmPasswordResetRequest>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let invalid_token = || (StatusCode::BAD_REQUEST, "Token is not valid".to_string());
+
+    let user_id = find_user_token(
+        &database_conn,
+        &user_request.token,
+        TokenPurpose::PasswordReset,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+    .ok_or_else(invalid_token)?;
+
+    let user = users::Entity::find_by_id(user_id)
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    // Checked before using the token, so the user can try another password
+    config
+        .password_policy
+        .check(&user.username, &user_request.password)
+        .await?;
+
+    consume_user_token(
+        &database_conn,
+        &user_request.token,
+        TokenPurpose::PasswordReset,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+    .ok_or_else(invalid_token)?;
+
+    let password = config
+        .password_hashing
+        .hash(&user_request.password)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let mut user: users::ActiveModel = user.into();
+    user.password = Set(password);
+    user.update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    discard_user_tokens(&database_conn, user_id, TokenPurpose::PasswordReset)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    revoke_all_tokens(&database_conn, user_id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(())
+}
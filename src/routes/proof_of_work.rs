@@ -0,0 +1,42 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    jwt_keys::JwtKeys,
+    proof_of_work::{PowSolution, ProofOfWork},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PowChallengeResponse {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+/// Puzzle to solve before `/register` or `/login`: find a `solution` such that
+/// the SHA-256 of `<challenge>:<solution>` starts with `difficulty` zero bits.
+pub async fn get_pow_challenge(
+    State(jwt_keys): State<JwtKeys>,
+    State(proof_of_work): State<ProofOfWork>,
+) -> Result<Json<PowChallengeResponse>, (StatusCode, String)> {
+    let (challenge, difficulty) = proof_of_work
+        .issue(&jwt_keys)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(Json(PowChallengeResponse {
+        challenge,
+        difficulty,
+    }))
+}
+
+/// Rejects requests whose proof of work is missing, wrong or already used.
+pub async fn check_pow_solution(
+    jwt_keys: &JwtKeys,
+    proof_of_work: &ProofOfWork,
+    solution: &PowSolution,
+) -> Result<(), (StatusCode, String)> {
+    proof_of_work
+        .verify(jwt_keys, solution)
+        .await
+        .map_err(|errors| (StatusCode::BAD_REQUEST, errors))
+}
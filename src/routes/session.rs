@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    prelude::DateTimeWithTimeZone, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::sessions,
+    middlewares::auth_middleware::{AuthenticatedUser, Credential},
+    utils::{refresh_token::REFRESH_TOKEN_DAYS, session::revoke_session},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionResponse {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: String,
+    pub created_at: DateTimeWithTimeZone,
+    pub last_seen_at: DateTimeWithTimeZone,
+    /// Whether it is the session of the token used for the call.
+    pub current: bool,
+}
+
+/// Sessions that weren't revoked and whose refresh token could still be valid,
+/// most recently seen first.
+pub async fn get_sessions(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<Vec<SessionResponse>>, (StatusCode, String)> {
+    let current_sid = match user.credential {
+        Credential::Token { sid, .. } => sid,
+        Credential::ApiKey { .. } => None,
+    };
+
+    let sessions = sessions::Entity::find()
+        .filter(sessions::Column::UserId.eq(user.id))
+        .filter(sessions::Column::RevokedAt.is_null())
+        .filter(sessions::Column::LastSeenAt.gt(Utc::now() - Duration::days(REFRESH_TOKEN_DAYS)))
+        .order_by_desc(sessions::Column::LastSeenAt)
+        .all(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: Some(session.id) == current_sid,
+            id: session.id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+        })
+        .collect();
+
+    Ok(Json(sessions))
+}
+
+/// Logs a device out, its tokens stop working right away.
+pub async fn delete_session(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    let session = sessions::Entity::find_by_id(id)
+        .filter(sessions::Column::UserId.eq(user.id))
+        .filter(sessions::Column::RevokedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+
+    revoke_session(&database_conn, session)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(())
+}
@@ -7,7 +7,7 @@ use sea_orm::{
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
-use crate::database::tasks;
+use crate::{database::tasks, middlewares::auth_middleware::AuthenticatedUser};
 
 #[derive(Deserialize, Serialize, Validate, Debug)]
 pub struct TaskRequest {
@@ -34,6 +34,7 @@ pub struct GetTaskQueryParams {
 }
 
 pub async fn create_task(
+    user: AuthenticatedUser,
     State(database_conn): State<DatabaseConnection>,
     Json(request): Json<TaskRequest>,
 ) -> Result<(), (StatusCode, String)> {
@@ -45,6 +46,7 @@ pub async fn create_task(
         title: Set(request.title),
         priority: Set(request.priority),
         description: Set(request.description),
+        user_id: Set(Some(user.id)),
         ..Default::default()
     };
 
@@ -57,10 +59,12 @@ pub async fn create_task(
 }
 
 pub async fn get_task(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
     State(database_conn): State<DatabaseConnection>,
 ) -> Result<Json<TaskResponse>, (StatusCode, String)> {
     let task = tasks::Entity::find_by_id(id)
+        .filter(tasks::Column::UserId.eq(user.id))
         .one(&database_conn)
         .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)));
@@ -80,6 +84,7 @@ pub async fn get_task(
 }
 
 pub async fn get_all_tasks(
+    user: AuthenticatedUser,
     State(database_conn): State<DatabaseConnection>,
     query_params: Option<Query<GetTaskQueryParams>>,
 ) -> Result<Json<Vec<TaskResponse>>, (StatusCode, String)> {
@@ -94,6 +99,7 @@ pub async fn get_all_tasks(
     };
 
     let tasks = tasks::Entity::find()
+        .filter(tasks::Column::UserId.eq(user.id))
         .filter(priority_filter)
         .all(&database_conn)
         .await
@@ -111,6 +117,7 @@ pub async fn get_all_tasks(
 }
 
 pub async fn update_task(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
     State(database_conn): State<DatabaseConnection>,
     Json(request): Json<TaskRequest>,
@@ -120,6 +127,7 @@ pub async fn update_task(
     }
 
     let task: Option<tasks::Model> = tasks::Entity::find_by_id(id)
+        .filter(tasks::Column::UserId.eq(user.id))
         .one(&database_conn)
         .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
@@ -150,16 +158,17 @@ pub async fn update_task(
 }
 
 pub async fn delete_task(
+    user: AuthenticatedUser,
     Path(id): Path<i32>,
     State(database_conn): State<DatabaseConnection>,
 ) -> Result<(), (StatusCode, String)> {
     let task: Option<tasks::Model> = tasks::Entity::find_by_id(id)
+        .filter(tasks::Column::UserId.eq(user.id))
         .one(&database_conn)
         .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
 
     if let Some(task) = task {
-        let task: tasks::Model = task.into();
         let res: DeleteResult = task
             .delete(&database_conn)
             .await
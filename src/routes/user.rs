@@ -1,24 +1,58 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
-use bcrypt::{hash, DEFAULT_COST};
+use chrono::{Duration, Utc};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, DeleteResult, EntityTrait, ModelTrait,
-    QueryFilter, Set, TryIntoModel,
+    prelude::DateTimeWithTimeZone,
+    sea_query::{Expr, LikeExpr},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait,
+    FromQueryResult, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+    TryIntoModel,
 };
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
-use crate::database::users;
+use crate::{
+    config::Config,
+    database::{api_keys, sessions, tasks, user_identities, users},
+    middlewares::auth_middleware::{forbidden, AuthenticatedUser, Credential},
+    routes::{
+        auth::too_many_attempts, password::send_password_reset_mail,
+        proof_of_work::check_pow_solution, verification::send_verification_mail,
+    },
+    utils::{
+        audit::{
+            record_audit_event, IMPERSONATION_STARTED, PASSWORD_RESET_FORCED, ROLE_CHANGED,
+            SESSIONS_REVOKED, USER_CREATED, USER_DELETED, USER_DISABLED, USER_ENABLED,
+            USER_RESTORED,
+        },
+        jwt::create_impersonation_token,
+        jwt_keys::JwtKeys,
+        ldap::LDAP_ISSUER,
+        login_throttle::LoginThrottle,
+        mailer::Mailer,
+        opaque_token::generate_token,
+        password_hash::verify_password,
+        proof_of_work::{PowSolution, ProofOfWork},
+        refresh_token::REFRESH_TOKEN_DAYS,
+        revocation::{revoke_all_tokens, revoke_other_tokens},
+        roles::Role,
+        session::ClientInfo,
+        user_token::{discard_user_tokens, TokenPurpose},
+    },
+};
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreateUserRequest {
     #[validate(email(message = "must be a valid email"))]
     pub username: String,
-    #[validate(length(min = 8, message = "must have at least 8 characters"))]
+    /// Checked against `config.password_policy`.
     pub password: String,
+    pub pow: PowSolution,
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
@@ -27,69 +61,294 @@ pub struct CreateUserResponse {
     pub username: String,
 }
 
+/// Accounts start unverified, a verification link is mailed to the username.
 pub async fn create_user(
     State(database_conn): State<DatabaseConnection>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    State(jwt_keys): State<JwtKeys>,
+    State(proof_of_work): State<ProofOfWork>,
     Json(user_request): Json<CreateUserRequest>,
 ) -> Result<Json<CreateUserResponse>, (StatusCode, String)> {
     if let Err(errors) = user_request.validate() {
         return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
     }
 
+    check_pow_solution(&jwt_keys, &proof_of_work, &user_request.pow).await?;
+
+    let new_user = create_account(
+        &database_conn,
+        &config,
+        user_request.username,
+        &user_request.password,
+        Role::User,
+    )
+    .await?;
+
+    if let Err(errors) =
+        send_verification_mail(&database_conn, mailer.as_ref(), &config, &new_user).await
+    {
+        eprintln!("Failed to send verification mail: {}", errors);
+    }
+
+    Ok(Json(CreateUserResponse {
+        id: new_user.id,
+        username: new_user.username,
+    }))
+}
+
+/// Saves an unverified account once the password passes the policy. The
+/// caller mails it the verification link.
+async fn create_account<C: ConnectionTrait>(
+    database_conn: &C,
+    config: &Config,
+    username: String,
+    password: &str,
+    role: Role,
+) -> Result<users::Model, (StatusCode, String)> {
+    config.password_policy.check(&username, password).await?;
+
     if let Some(_new_user) = users::Entity::find()
-        .filter(users::Column::Username.eq(user_request.username.clone()))
-        .one(&database_conn)
+        .filter(users::Column::Username.eq(username.clone()))
+        .one(database_conn)
         .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
     {
-        return Err((StatusCode::BAD_REQUEST, format!("User already exists")));
+        return Err((StatusCode::BAD_REQUEST, "User already exists".to_string()));
     }
 
-    let new_user = hash(user_request.password, DEFAULT_COST)
-        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    let new_user = config
+        .password_hashing
+        .hash(password)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
 
     let new_user = users::ActiveModel {
-        username: Set(user_request.username),
+        username: Set(username),
         password: Set(new_user),
+        role: Set(role.as_str().to_string()),
         ..Default::default()
     };
 
     let new_user = new_user
-        .save(&database_conn)
+        .save(database_conn)
         .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
         .try_into_model()
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
 
+    Ok(new_user)
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct AdminCreateUserRequest {
+    #[validate(email(message = "must be a valid email"))]
+    pub username: String,
+    /// Checked against `config.password_policy`.
+    pub password: String,
+    pub role: Role,
+}
+
+/// Same as a registration, without the proof of work and with a chosen role.
+pub async fn admin_create_user(
+    admin: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    Json(user_request): Json<AdminCreateUserRequest>,
+) -> Result<Json<CreateUserResponse>, (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let new_user = create_account(
+        &transaction,
+        &config,
+        user_request.username,
+        &user_request.password,
+        user_request.role,
+    )
+    .await?;
+
+    record_audit_event(
+        &transaction,
+        Some(new_user.id),
+        Some(admin.id),
+        USER_CREATED,
+        Some(format!("role {}", new_user.role)),
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if let Err(errors) =
+        send_verification_mail(&database_conn, mailer.as_ref(), &config, &new_user).await
+    {
+        eprintln!("Failed to send verification mail: {}", errors);
+    }
+
     Ok(Json(CreateUserResponse {
         id: new_user.id,
         username: new_user.username,
     }))
 }
 
+fn first_page() -> u64 {
+    1
+}
+
+fn default_per_page() -> u64 {
+    20
+}
+
+/// Keeps the offset of a page, `page * per_page`, far from overflowing.
+const MAX_PAGE: u64 = 1_000_000;
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
-pub struct GetAllUsersResponse {
+pub struct GetAllUsersParams {
+    /// Beginning of the username.
+    #[validate(length(max = 64, message = "must have at most 64 characters"))]
+    pub search: Option<String>,
+    pub role: Option<Role>,
+    pub verified: Option<bool>,
+    /// Lists the deleted users instead of the active ones.
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default = "first_page")]
+    #[validate(range(min = 1, max = "MAX_PAGE", message = "must be between 1 and 1000000"))]
+    pub page: u64,
+    #[serde(default = "default_per_page")]
+    #[validate(range(min = 1, max = 100, message = "must be between 1 and 100"))]
+    pub per_page: u64,
+}
+
+/// What an admin sees of an account, credentials are never part of it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSummaryResponse {
     pub id: i32,
     pub username: String,
-    pub password: String,
+    pub role: String,
+    pub display_name: Option<String>,
+    pub verified_at: Option<DateTimeWithTimeZone>,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    pub disabled_at: Option<DateTimeWithTimeZone>,
+    pub mfa_enabled: bool,
+    /// Tasks that weren't deleted.
+    pub task_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetAllUsersResponse {
+    pub users: Vec<UserSummaryResponse>,
+    pub page: u64,
+    pub per_page: u64,
+    /// Users matching the filters, on every page.
+    pub total: u64,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct TaskCount {
+    user_id: i32,
+    task_count: i64,
 }
 
+/// Users matching the filters, by id.
 #[axum_macros::debug_handler]
 pub async fn get_all_users(
     State(database_conn): State<DatabaseConnection>,
-) -> Result<Json<Vec<GetAllUsersResponse>>, (StatusCode, String)> {
-    let users: Vec<GetAllUsersResponse> = users::Entity::find()
+    Query(params): Query<GetAllUsersParams>,
+) -> Result<Json<GetAllUsersResponse>, (StatusCode, String)> {
+    if let Err(errors) = params.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let mut condition = Condition::all().add(if params.deleted {
+        users::Column::DeletedAt.is_not_null()
+    } else {
+        users::Column::DeletedAt.is_null()
+    });
+    if let Some(search) = &params.search {
+        // The prefix is matched literally, wildcards included
+        let pattern = format!(
+            "{}%",
+            search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        condition = condition.add(
+            Expr::tbl(users::Entity, users::Column::Username)
+                .like(LikeExpr::new(pattern).escape('\\')),
+        );
+    }
+    if let Some(role) = params.role {
+        condition = condition.add(users::Column::Role.eq(role.as_str()));
+    }
+    if let Some(verified) = params.verified {
+        condition = condition.add(if verified {
+            users::Column::VerifiedAt.is_not_null()
+        } else {
+            users::Column::VerifiedAt.is_null()
+        });
+    }
+
+    let paginator = users::Entity::find()
+        .filter(condition)
+        .order_by_asc(users::Column::Id)
+        .paginate(&database_conn, params.per_page);
+    let total = paginator
+        .num_items()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    let users = paginator
+        .fetch_page(params.page - 1)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let task_counts: HashMap<i32, i64> = tasks::Entity::find()
+        .select_only()
+        .column(tasks::Column::UserId)
+        .column_as(tasks::Column::Id.count(), "task_count")
+        .filter(tasks::Column::UserId.is_in(users.iter().map(|user| user.id)))
+        .filter(tasks::Column::DeletedAt.is_null())
+        .group_by(tasks::Column::UserId)
+        .into_model::<TaskCount>()
         .all(&database_conn)
         .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
         .into_iter()
-        .map(|users| GetAllUsersResponse {
-            id: users.id,
-            username: users.username,
-            password: users.password,
+        .map(|count| (count.user_id, count.task_count))
+        .collect();
+
+    let users = users
+        .into_iter()
+        .map(|user| UserSummaryResponse {
+            task_count: task_counts.get(&user.id).copied().unwrap_or(0),
+            id: user.id,
+            username: user.username,
+            role: user.role,
+            display_name: user.display_name,
+            verified_at: user.verified_at,
+            deleted_at: user.deleted_at,
+            disabled_at: user.disabled_at,
+            mfa_enabled: user.totp_enabled_at.is_some(),
         })
         .collect();
 
-    Ok(Json(users))
+    Ok(Json(GetAllUsersResponse {
+        users,
+        page: params.page,
+        per_page: params.per_page,
+        total,
+    }))
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
@@ -98,7 +357,330 @@ pub struct DeleteUserByUsernameRequest {
     pub username: String,
 }
 
+/// The user of the path, unless deleted.
+async fn find_user_by_username(
+    database_conn: &DatabaseConnection,
+    username: DeleteUserByUsernameRequest,
+) -> Result<users::Model, (StatusCode, String)> {
+    if let Err(errors) = username.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    users::Entity::find()
+        .filter(users::Column::Username.eq(username.username))
+        .filter(users::Column::DeletedAt.is_null())
+        .one(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))
+}
+
+/// Everything an admin sees of one account, still without credentials.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserDetailsResponse {
+    pub id: i32,
+    pub username: String,
+    pub role: String,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub verified_at: Option<DateTimeWithTimeZone>,
+    pub disabled_at: Option<DateTimeWithTimeZone>,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    pub mfa_enabled: bool,
+    /// Tasks that weren't deleted.
+    pub task_count: u64,
+    pub session_count: u64,
+    pub api_key_count: u64,
+}
+
+/// Deleted users can be looked at too, until they are purged.
+pub async fn get_user(
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<UserDetailsResponse>, (StatusCode, String)> {
+    if let Err(errors) = username.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(username.username))
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    let task_count = tasks::Entity::find()
+        .filter(tasks::Column::UserId.eq(user.id))
+        .filter(tasks::Column::DeletedAt.is_null())
+        .count(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    let session_count = sessions::Entity::find()
+        .filter(sessions::Column::UserId.eq(user.id))
+        .filter(sessions::Column::RevokedAt.is_null())
+        .filter(sessions::Column::LastSeenAt.gt(Utc::now() - Duration::days(REFRESH_TOKEN_DAYS)))
+        .count(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    let api_key_count = api_keys::Entity::find()
+        .filter(api_keys::Column::UserId.eq(user.id))
+        .count(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(Json(UserDetailsResponse {
+        id: user.id,
+        username: user.username,
+        role: user.role,
+        display_name: user.display_name,
+        timezone: user.timezone,
+        locale: user.locale,
+        verified_at: user.verified_at,
+        disabled_at: user.disabled_at,
+        deleted_at: user.deleted_at,
+        mfa_enabled: user.totp_enabled_at.is_some(),
+        task_count,
+        session_count,
+        api_key_count,
+    }))
+}
+
+/// Locks the user out without deleting anything: every session ends, and
+/// logins, token renewals and API keys are refused until it is enabled again.
+pub async fn disable_user(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    let user = find_user_by_username(&database_conn, username).await?;
+
+    if user.id == admin.id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You can't disable yourself".to_string(),
+        ));
+    }
+    if user.disabled_at.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "User is already disabled".to_string(),
+        ));
+    }
+
+    // The change, its revocations and its audit record go together
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.disabled_at = Set(Some(Utc::now().into()));
+    user.update(&transaction)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    revoke_all_tokens(&transaction, user_id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user_id),
+        Some(admin.id),
+        USER_DISABLED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(())
+}
+
+pub async fn enable_user(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    let user = find_user_by_username(&database_conn, username).await?;
+
+    if user.disabled_at.is_none() {
+        return Err((StatusCode::BAD_REQUEST, "User is not disabled".to_string()));
+    }
+
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.disabled_at = Set(None);
+    user.update(&transaction)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user_id),
+        Some(admin.id),
+        USER_ENABLED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(())
+}
+
+/// Replaces the password with a random one nobody knows, logs the user out
+/// everywhere and mails a reset link.
+pub async fn force_password_reset(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+) -> Result<(), (StatusCode, String)> {
+    let user = find_user_by_username(&database_conn, username).await?;
+
+    let password = config
+        .password_hashing
+        .hash(&generate_token())
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let mut active_user: users::ActiveModel = user.clone().into();
+    active_user.password = Set(password);
+    active_user
+        .update(&transaction)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    revoke_all_tokens(&transaction, user.id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user.id),
+        Some(admin.id),
+        PASSWORD_RESET_FORCED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    // The reset is done, the user can still ask for another link
+    if let Err(errors) =
+        send_password_reset_mail(&database_conn, mailer.as_ref(), &config, &user).await
+    {
+        eprintln!("Failed to send password reset mail: {}", errors);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ChangeRoleRequest {
+    pub role: Role,
+}
+
+/// The user is logged out, so that the tokens carrying the old role stop
+/// working right away.
+pub async fn change_user_role(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+    Json(role_request): Json<ChangeRoleRequest>,
+) -> Result<(), (StatusCode, String)> {
+    let user = find_user_by_username(&database_conn, username).await?;
+
+    // Keeps the last admin from demoting itself by mistake
+    if user.id == admin.id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You can't change your own role".to_string(),
+        ));
+    }
+    // The directory owns these roles, a change would be undone at the next
+    // login
+    let directory_identity = user_identities::Entity::find()
+        .filter(user_identities::Column::UserId.eq(user.id))
+        .filter(user_identities::Column::Issuer.eq(LDAP_ISSUER))
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    if directory_identity.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The role of a directory user is set by its groups".to_string(),
+        ));
+    }
+    if user.role == role_request.role.as_str() {
+        return Ok(());
+    }
+
+    let details = format!("{} -> {}", user.role, role_request.role.as_str());
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.role = Set(role_request.role.as_str().to_string());
+    user.update(&transaction)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    revoke_all_tokens(&transaction, user_id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user_id),
+        Some(admin.id),
+        ROLE_CHANGED,
+        Some(details),
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(())
+}
+
+/// Soft-deletes the user, who is logged out everywhere and can't log in
+/// anymore. The account can be restored until it is purged, see
+/// `utils::user_purge`.
 pub async fn delete_user_by_username(
+    admin: AuthenticatedUser,
     Path(username): Path<DeleteUserByUsernameRequest>,
     State(database_conn): State<DatabaseConnection>,
 ) -> Result<(), (StatusCode, String)> {
@@ -108,19 +690,399 @@ pub async fn delete_user_by_username(
 
     let user = users::Entity::find()
         .filter(users::Column::Username.eq(username.username))
+        .filter(users::Column::DeletedAt.is_null())
         .one(&database_conn)
         .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.deleted_at = Set(Some(Utc::now().into()));
+    user.update(&transaction)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    revoke_all_tokens(&transaction, user_id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user_id),
+        Some(admin.id),
+        USER_DELETED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
         .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
 
-    if let Some(user) = user {
-        let user: users::Model = user.into();
-        let res: DeleteResult = user
-            .delete(&database_conn)
-            .await
-            .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
-        assert_eq!(res.rows_affected, 1);
+    Ok(())
+}
+
+/// Undoes a soft-delete. The user logs in again as before, sessions and
+/// tokens revoked by the deletion stay revoked.
+pub async fn restore_user(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = username.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(username.username))
+        .filter(users::Column::DeletedAt.is_not_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "Deleted user not found".to_string()))?;
+
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.deleted_at = Set(None);
+    user.update(&transaction)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user_id),
+        Some(admin.id),
+        USER_RESTORED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(())
+}
+
+pub async fn revoke_user_sessions(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = username.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(username.username))
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    let transaction = database_conn
+        .begin()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    revoke_all_tokens(&transaction, user.id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    record_audit_event(
+        &transaction,
+        Some(user.id),
+        Some(admin.id),
+        SESSIONS_REVOKED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationResponse {
+    pub token: String,
+}
+
+/// Gives the admin a short-lived token acting as the user, to reproduce an
+/// issue. It can't reach the account or administration routes, and every
+/// request made with it is recorded with the admin as actor.
+pub async fn impersonate_user(
+    admin: AuthenticatedUser,
+    Path(username): Path<DeleteUserByUsernameRequest>,
+    State(jwt_keys): State<JwtKeys>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<ImpersonationResponse>, (StatusCode, String)> {
+    if let Err(errors) = username.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    // Only someone who logged in can be held accountable
+    if let Credential::ApiKey { .. } = admin.credential {
+        return Err(forbidden());
+    }
+
+    // A disabled user has no session to act in either
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(username.username))
+        .filter(users::Column::DeletedAt.is_null())
+        .filter(users::Column::DisabledAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    if user.id == admin.id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You can't impersonate yourself".to_string(),
+        ));
+    }
+
+    let role =
+        Role::from_str(&user.role).map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let token = create_impersonation_token(&jwt_keys, user.id, role, admin.id)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    record_audit_event(
+        &database_conn,
+        Some(user.id),
+        Some(admin.id),
+        IMPERSONATION_STARTED,
+        None,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(Json(ImpersonationResponse { token }))
+}
+
+/// `Area/Location` names of the IANA database, or `UTC`. Empty clears the
+/// field.
+fn validate_timezone(timezone: &str) -> Result<(), ValidationError> {
+    let is_valid = timezone.is_empty()
+        || timezone.split('/').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        });
+
+    if is_valid {
         Ok(())
     } else {
-        Err((StatusCode::NOT_FOUND, "User not found".to_string()))
+        Err(ValidationError::new("timezone"))
+    }
+}
+
+/// BCP 47 language tags, such as `fr` or `en-GB`. Empty clears the field.
+fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+
+    let is_valid = locale.is_empty()
+        || (2..=3).contains(&language.len())
+            && language.chars().all(|c| c.is_ascii_alphabetic())
+            && subtags.all(|subtag| {
+                (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("locale"))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileResponse {
+    pub id: i32,
+    pub username: String,
+    pub role: String,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub verified_at: Option<DateTimeWithTimeZone>,
+    pub mfa_enabled: bool,
+}
+
+impl From<users::Model> for ProfileResponse {
+    fn from(user: users::Model) -> Self {
+        ProfileResponse {
+            id: user.id,
+            username: user.username,
+            role: user.role,
+            display_name: user.display_name,
+            timezone: user.timezone,
+            locale: user.locale,
+            verified_at: user.verified_at,
+            mfa_enabled: user.totp_enabled_at.is_some(),
+        }
     }
 }
+
+async fn find_own_user(
+    database_conn: &DatabaseConnection,
+    user: &AuthenticatedUser,
+) -> Result<users::Model, (StatusCode, String)> {
+    users::Entity::find_by_id(user.id)
+        .one(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))
+}
+
+pub async fn get_me(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<ProfileResponse>, (StatusCode, String)> {
+    let user = find_own_user(&database_conn, &user).await?;
+
+    Ok(Json(user.into()))
+}
+
+/// Missing fields are left as they are, an empty string clears one.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateProfileRequest {
+    #[validate(length(max = 64, message = "must have at most 64 characters"))]
+    pub display_name: Option<String>,
+    #[validate(
+        length(max = 64, message = "must have at most 64 characters"),
+        custom(function = "validate_timezone", message = "must be an IANA time zone")
+    )]
+    pub timezone: Option<String>,
+    #[validate(
+        length(max = 35, message = "must have at most 35 characters"),
+        custom(
+            function = "validate_locale",
+            message = "must be a BCP 47 language tag"
+        )
+    )]
+    pub locale: Option<String>,
+}
+
+pub async fn update_me(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    Json(user_request): Json<UpdateProfileRequest>,
+) -> Result<Json<ProfileResponse>, (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let non_empty = |value: String| Some(value).filter(|value| !value.is_empty());
+
+    let user = find_own_user(&database_conn, &user).await?;
+
+    let mut user: users::ActiveModel = user.into();
+    if let Some(display_name) = user_request.display_name {
+        user.display_name = Set(non_empty(display_name.trim().to_string()));
+    }
+    if let Some(timezone) = user_request.timezone {
+        user.timezone = Set(non_empty(timezone));
+    }
+    if let Some(locale) = user_request.locale {
+        user.locale = Set(non_empty(locale));
+    }
+
+    let user = user
+        .update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(Json(user.into()))
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    /// Checked against `config.password_policy`.
+    pub new_password: String,
+}
+
+/// Sets a new password and logs the user out of every other session. Wrong
+/// current passwords count as failed logins.
+pub async fn change_password(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+    State(login_throttle): State<LoginThrottle>,
+    State(config): State<Config>,
+    client: ClientInfo,
+    Json(user_request): Json<ChangePasswordRequest>,
+) -> Result<(), (StatusCode, String)> {
+    let current_sid = match user.credential {
+        Credential::Token { sid, .. } => sid,
+        Credential::ApiKey { .. } => None,
+    };
+    let user = find_own_user(&database_conn, &user).await?;
+
+    if login_throttle
+        .locked_for(&user.username, &client.ip)
+        .is_some()
+    {
+        return Err(too_many_attempts());
+    }
+
+    let is_valid = verify_password(&user_request.current_password, &user.password)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+    if !is_valid {
+        login_throttle.record_failure(&user.username, &client.ip);
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Current password is not valid".to_string(),
+        ));
+    }
+
+    config
+        .password_policy
+        .check(&user.username, &user_request.new_password)
+        .await?;
+
+    let password = config
+        .password_hashing
+        .hash(&user_request.new_password)
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    let user_id = user.id;
+    let mut user: users::ActiveModel = user.into();
+    user.password = Set(password);
+    user.update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    // A reset link mailed before is no longer wanted
+    discard_user_tokens(&database_conn, user_id, TokenPurpose::PasswordReset)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    revoke_other_tokens(&database_conn, user_id, current_sid)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(())
+}
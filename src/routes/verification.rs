@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    config::Config,
+    database::{user_tokens, users},
+    utils::{
+        mailer::{Mail, Mailer},
+        opaque_token::TOKEN_LENGTH,
+        user_token::{consume_user_token, discard_user_tokens, issue_user_token, TokenPurpose},
+    },
+};
+
+const VERIFICATION_TOKEN_HOURS: i64 = 24;
+/// Minimum delay between two verification mails to the same account
+const RESEND_COOLDOWN_SECONDS: i64 = 60;
+/// Maximum number of verification mails to the same account per hour
+const RESEND_HOURLY_LIMIT: u64 = 5;
+
+pub async fn send_verification_mail(
+    database_conn: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    config: &Config,
+    user: &users::Model,
+) -> Result<(), String> {
+    let token = issue_user_token(
+        database_conn,
+        user.id,
+        TokenPurpose::EmailVerification,
+        Duration::hours(VERIFICATION_TOKEN_HOURS),
+    )
+    .await?;
+
+    mailer
+        .send(Mail {
+            to: user.username.clone(),
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "Follow this link to verify your email:\n{}/verify_email?token={}\n\nIt expires in {} hours.",
+                config.app_url, token, VERIFICATION_TOKEN_HOURS
+            ),
+        })
+        .await
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct VerifyEmailRequest {
+    #[validate(length(equal = "TOKEN_LENGTH", message = "Token is not valid"))]
+    pub token: String,
+}
+
+pub async fn verify_email(
+    State(database_conn): State<DatabaseConnection>,
+    Json(user_request): Json<VerifyEmailRequest>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user_id = consume_user_token(
+        &database_conn,
+        &user_request.token,
+        TokenPurpose::EmailVerification,
+    )
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+    .ok_or((StatusCode::BAD_REQUEST, "Token is not valid".to_string()))?;
+
+    let user = users::Entity::find_by_id(user_id)
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    if user.verified_at.is_none() {
+        let mut user: users::ActiveModel = user.into();
+        user.verified_at = Set(Some(Utc::now().into()));
+        user.update(&database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    }
+
+    discard_user_tokens(&database_conn, user_id, TokenPurpose::EmailVerification)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ResendVerificationRequest {
+    #[validate(email(message = "must be a valid email"))]
+    pub username: String,
+}
+
+/// Succeeds without sending anything for unknown or verified accounts.
+pub async fn resend_verification(
+    State(database_conn): State<DatabaseConnection>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    Json(user_request): Json<ResendVerificationRequest>,
+) -> Result<(), (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(user_request.username))
+        .filter(users::Column::VerifiedAt.is_null())
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let now = Utc::now();
+    let recent_mails = |since: chrono::DateTime<Utc>| {
+        user_tokens::Entity::find()
+            .filter(user_tokens::Column::UserId.eq(user.id))
+            .filter(user_tokens::Column::Purpose.eq(TokenPurpose::EmailVerification.as_str()))
+            .filter(user_tokens::Column::CreatedAt.gt(since))
+            .count(&database_conn)
+    };
+
+    let last_minute = recent_mails(now - Duration::seconds(RESEND_COOLDOWN_SECONDS))
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    let last_hour = recent_mails(now - Duration::hours(1))
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if last_minute > 0 || last_hour >= RESEND_HOURLY_LIMIT {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many verification mails, try again later".to_string(),
+        ));
+    }
+
+    send_verification_mail(&database_conn, mailer.as_ref(), &config, &user)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok(())
+}
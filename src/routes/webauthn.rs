@@ -0,0 +1,465 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use sea_orm::{
+    prelude::DateTimeWithTimeZone, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    database::{users, webauthn_credentials},
+    middlewares::auth_middleware::{unauthorized, AuthenticatedUser},
+    routes::auth::{finish_login, start_session, LoginResponse},
+    utils::{
+        jwt_keys::JwtKeys,
+        session::ClientInfo,
+        session_cookie::SessionParams,
+        webauthn::{
+            decode_base64url, parse_attestation_object, verify_assertion, AuthenticatorData,
+            Ceremony, CredentialPublicKey, Webauthn, CEREMONY_TIMEOUT, EDDSA, ES256,
+        },
+    },
+};
+
+const RP_NAME: &str = "axum-webapp";
+
+// The ceremony types follow the JSON serialization of the WebAuthn spec, so
+// that browsers can pass them to `navigator.credentials` and send back the
+// result of `PublicKeyCredential.toJSON()` as is.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnUser {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+impl CredentialDescriptor {
+    fn public_key(id: String) -> Self {
+        CredentialDescriptor {
+            credential_type: "public-key".to_string(),
+            id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: WebauthnUser,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u128,
+    pub attestation: String,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u128,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct WebauthnRegistrationRequest {
+    #[validate(length(min = 1, max = 64, message = "must have between 1 and 64 characters"))]
+    pub name: String,
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebauthnLoginRequest {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct WebauthnLoginStartRequest {
+    #[validate(email(message = "must be a valid email"))]
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebauthnCredentialResponse {
+    pub id: i32,
+    pub name: String,
+    pub last_used_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+impl From<webauthn_credentials::Model> for WebauthnCredentialResponse {
+    fn from(credential: webauthn_credentials::Model) -> Self {
+        WebauthnCredentialResponse {
+            id: credential.id,
+            name: credential.name,
+            last_used_at: credential.last_used_at,
+            created_at: credential.created_at,
+        }
+    }
+}
+
+/// The user handle stored by authenticators, it must not contain the email.
+fn user_handle(user_id: i32) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.to_be_bytes())
+}
+
+fn bad_request(errors: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, errors)
+}
+
+async fn credential_descriptors(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+) -> Result<Vec<CredentialDescriptor>, (StatusCode, String)> {
+    Ok(webauthn_credentials::Entity::find()
+        .filter(webauthn_credentials::Column::UserId.eq(user_id))
+        .all(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .into_iter()
+        .map(|credential| CredentialDescriptor::public_key(credential.credential_id))
+        .collect())
+}
+
+/// Options to pass to `navigator.credentials.create()`.
+pub async fn start_webauthn_registration(
+    user: AuthenticatedUser,
+    State(webauthn): State<Webauthn>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<CreationOptions>, (StatusCode, String)> {
+    let username = users::Entity::find_by_id(user.id)
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or_else(unauthorized)?
+        .username;
+
+    Ok(Json(CreationOptions {
+        challenge: webauthn.start(Ceremony::Registration, Some(user.id)),
+        rp: RelyingParty {
+            id: webauthn.rp_id().to_string(),
+            name: RP_NAME.to_string(),
+        },
+        user: WebauthnUser {
+            id: user_handle(user.id),
+            name: username.clone(),
+            display_name: username,
+        },
+        pub_key_cred_params: [ES256, EDDSA]
+            .into_iter()
+            .map(|alg| CredentialParameters {
+                credential_type: "public-key".to_string(),
+                alg,
+            })
+            .collect(),
+        timeout: CEREMONY_TIMEOUT.as_millis(),
+        attestation: "none".to_string(),
+        exclude_credentials: credential_descriptors(&database_conn, user.id).await?,
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "preferred".to_string(),
+            user_verification: "preferred".to_string(),
+        },
+    }))
+}
+
+/// Stores the credential created by the authenticator.
+pub async fn finish_webauthn_registration(
+    user: AuthenticatedUser,
+    State(webauthn): State<Webauthn>,
+    State(database_conn): State<DatabaseConnection>,
+    Json(user_request): Json<WebauthnRegistrationRequest>,
+) -> Result<Json<WebauthnCredentialResponse>, (StatusCode, String)> {
+    if let Err(errors) = user_request.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+    }
+
+    let client_data_json =
+        decode_base64url(&user_request.response.client_data_json).map_err(bad_request)?;
+    if webauthn
+        .verify_client_data(&client_data_json, Ceremony::Registration)
+        .map_err(bad_request)?
+        != Some(user.id)
+    {
+        return Err(bad_request("Challenge is not valid or expired".to_string()));
+    }
+
+    let attestation_object =
+        decode_base64url(&user_request.response.attestation_object).map_err(bad_request)?;
+    let authenticator_data = parse_attestation_object(&attestation_object).map_err(bad_request)?;
+    let authenticator_data =
+        AuthenticatorData::parse(&authenticator_data, webauthn.rp_id()).map_err(bad_request)?;
+
+    let attested_credential = authenticator_data
+        .attested_credential
+        .ok_or_else(|| bad_request("No credential was created".to_string()))?;
+    let credential_id = URL_SAFE_NO_PAD.encode(&attested_credential.credential_id);
+    if credential_id != user_request.id.trim_end_matches('=') {
+        return Err(bad_request("Credential id doesn't match".to_string()));
+    }
+
+    let existing = webauthn_credentials::Entity::find()
+        .filter(webauthn_credentials::Column::CredentialId.eq(credential_id.clone()))
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+    if existing.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            "Credential already registered".to_string(),
+        ));
+    }
+
+    let credential = webauthn_credentials::ActiveModel {
+        user_id: Set(user.id),
+        name: Set(user_request.name),
+        credential_id: Set(credential_id),
+        public_key: Set(attested_credential.public_key.key),
+        algorithm: Set(attested_credential.public_key.algorithm as i32),
+        sign_count: Set(authenticator_data.sign_count.into()),
+        ..Default::default()
+    }
+    .insert(&database_conn)
+    .await
+    .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    Ok(Json(credential.into()))
+}
+
+pub async fn get_webauthn_credentials(
+    user: AuthenticatedUser,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<Json<Vec<WebauthnCredentialResponse>>, (StatusCode, String)> {
+    let credentials = webauthn_credentials::Entity::find()
+        .filter(webauthn_credentials::Column::UserId.eq(user.id))
+        .order_by_asc(webauthn_credentials::Column::Id)
+        .all(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .into_iter()
+        .map(WebauthnCredentialResponse::from)
+        .collect();
+
+    Ok(Json(credentials))
+}
+
+pub async fn delete_webauthn_credential(
+    user: AuthenticatedUser,
+    Path(id): Path<i32>,
+    State(database_conn): State<DatabaseConnection>,
+) -> Result<(), (StatusCode, String)> {
+    let result = webauthn_credentials::Entity::delete_many()
+        .filter(webauthn_credentials::Column::Id.eq(id))
+        .filter(webauthn_credentials::Column::UserId.eq(user.id))
+        .exec(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    if result.rows_affected == 0 {
+        return Err((StatusCode::NOT_FOUND, "Credential not found".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Options to pass to `navigator.credentials.get()`. Without a username the
+/// authenticator offers the passkeys it has for the app.
+pub async fn start_webauthn_login(
+    State(webauthn): State<Webauthn>,
+    State(database_conn): State<DatabaseConnection>,
+    user_request: Option<Json<WebauthnLoginStartRequest>>,
+) -> Result<Json<RequestOptions>, (StatusCode, String)> {
+    let username = match user_request {
+        Some(Json(user_request)) => {
+            if let Err(errors) = user_request.validate() {
+                return Err((StatusCode::BAD_REQUEST, format!("{}", errors)));
+            }
+
+            user_request.username
+        }
+        None => None,
+    };
+
+    // Unknown users get no credentials rather than an error, so that the
+    // response doesn't tell which accounts exist
+    let user = match username {
+        Some(username) => users::Entity::find()
+            .filter(users::Column::Username.eq(username))
+            .filter(users::Column::DeletedAt.is_null())
+            .one(&database_conn)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?,
+        None => None,
+    };
+    let allow_credentials = match user {
+        Some(user) => credential_descriptors(&database_conn, user.id).await?,
+        None => Vec::new(),
+    };
+
+    Ok(Json(RequestOptions {
+        challenge: webauthn.start(Ceremony::Authentication, None),
+        rp_id: webauthn.rp_id().to_string(),
+        timeout: CEREMONY_TIMEOUT.as_millis(),
+        allow_credentials,
+        user_verification: "preferred".to_string(),
+    }))
+}
+
+/// Checks the assertion of the authenticator and logs its owner in. Users
+/// with two-factor authentication still get a challenge unless the
+/// authenticator verified them itself.
+pub async fn finish_webauthn_login(
+    State(jwt_keys): State<JwtKeys>,
+    State(webauthn): State<Webauthn>,
+    State(database_conn): State<DatabaseConnection>,
+    client: ClientInfo,
+    Query(params): Query<SessionParams>,
+    jar: CookieJar,
+    Json(user_request): Json<WebauthnLoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), (StatusCode, String)> {
+    let response = user_request.response;
+    let client_data_json =
+        decode_base64url(&response.client_data_json).map_err(|_| unauthorized())?;
+    webauthn
+        .verify_client_data(&client_data_json, Ceremony::Authentication)
+        .map_err(|_| unauthorized())?;
+
+    let credential = webauthn_credentials::Entity::find()
+        .filter(
+            webauthn_credentials::Column::CredentialId
+                .eq(user_request.id.trim_end_matches('=').to_string()),
+        )
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or_else(unauthorized)?;
+
+    if let Some(handle) = &response.user_handle {
+        if handle.trim_end_matches('=') != user_handle(credential.user_id) {
+            return Err(unauthorized());
+        }
+    }
+
+    let authenticator_data_bytes =
+        decode_base64url(&response.authenticator_data).map_err(|_| unauthorized())?;
+    let authenticator_data = AuthenticatorData::parse(&authenticator_data_bytes, webauthn.rp_id())
+        .map_err(|_| unauthorized())?;
+    let signature = decode_base64url(&response.signature).map_err(|_| unauthorized())?;
+
+    verify_assertion(
+        &CredentialPublicKey {
+            algorithm: credential.algorithm.into(),
+            key: credential.public_key.clone(),
+        },
+        &authenticator_data_bytes,
+        &client_data_json,
+        &signature,
+    )
+    .map_err(|_| unauthorized())?;
+
+    // Authenticators without a counter always send 0, otherwise a counter
+    // that didn't increase means the credential was cloned
+    let sign_count = i64::from(authenticator_data.sign_count);
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        return Err(unauthorized());
+    }
+
+    let user_id = credential.user_id;
+    let mut credential: webauthn_credentials::ActiveModel = credential.into();
+    credential.sign_count = Set(sign_count);
+    credential.last_used_at = Set(Some(Utc::now().into()));
+    credential
+        .update(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?;
+
+    let user = users::Entity::find_by_id(user_id)
+        .filter(users::Column::DeletedAt.is_null())
+        .one(&database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", errors)))?
+        .ok_or_else(unauthorized)?;
+
+    let (jar, response) = if authenticator_data.user_verified() {
+        start_session(
+            &database_conn,
+            &jwt_keys,
+            &user,
+            &client,
+            params.session,
+            jar,
+        )
+        .await?
+    } else {
+        finish_login(
+            &database_conn,
+            &jwt_keys,
+            &user,
+            &client,
+            params.session,
+            jar,
+        )
+        .await?
+    };
+
+    Ok((jar, Json(response)))
+}
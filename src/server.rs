@@ -1,26 +1,83 @@
-use crate::router::create_routes;
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::{
+    config::Config,
+    router::create_routes,
+    utils::{
+        authenticator::{Authenticator, ChainedAuthenticator, LocalAuthenticator},
+        jwt_keys::JwtKeys,
+        ldap::LdapAuthenticator,
+        login_throttle::LoginThrottle,
+        mailer::Mailer,
+        oidc::OidcClient,
+        proof_of_work::ProofOfWork,
+        revocation::prune_revoked_tokens_task,
+        user_purge::purge_deleted_users_task,
+        webauthn::Webauthn,
+    },
+};
 use axum_macros::FromRef;
 use sea_orm::{Database, DatabaseConnection};
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub database_conn: DatabaseConnection,
-    pub(crate) jwt_secret: String,
+    pub(crate) jwt_keys: JwtKeys,
+    pub mailer: Arc<dyn Mailer>,
+    pub config: Config,
+    pub login_throttle: LoginThrottle,
+    pub oidc: Option<OidcClient>,
+    pub webauthn: Webauthn,
+    pub authenticator: Arc<dyn Authenticator>,
+    pub proof_of_work: ProofOfWork,
 }
 
-pub async fn run(database_uri: String, jwt_secret: String) {
+pub async fn run(database_uri: String, jwt_keys: JwtKeys, mailer: Arc<dyn Mailer>, config: Config) {
     let database_conn = Database::connect(database_uri).await.unwrap();
 
+    tokio::spawn(prune_revoked_tokens_task(database_conn.clone()));
+    tokio::spawn(purge_deleted_users_task(
+        database_conn.clone(),
+        config.user_retention_days,
+    ));
+
+    let oidc = config
+        .oidc
+        .clone()
+        .map(|oidc| OidcClient::new(oidc, &config.app_url));
+    let webauthn = Webauthn::new(&config.app_url);
+    let proof_of_work = ProofOfWork::new(config.pow_difficulty);
+
+    // Local accounts keep working when the directory is enabled
+    let local: Arc<dyn Authenticator> = Arc::new(LocalAuthenticator::new(config.password_hashing));
+    let authenticator: Arc<dyn Authenticator> = match config.ldap.clone() {
+        Some(ldap) => Arc::new(ChainedAuthenticator(vec![
+            Arc::new(LdapAuthenticator::new(ldap, config.password_hashing)),
+            local,
+        ])),
+        None => local,
+    };
+
     let app_state = AppState {
         database_conn,
-        jwt_secret,
+        jwt_keys,
+        mailer,
+        config,
+        login_throttle: LoginThrottle::default(),
+        oidc,
+        webauthn,
+        authenticator,
+        proof_of_work,
     };
 
     let app = create_routes(app_state);
 
     // Start the server
     axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
-        .serve(app.await.into_make_service())
+        .serve(
+            app.await
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
         .await
         .unwrap();
 }
@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{app_test, bearer_request, login_test, register_test, response_json};
+    use axum::http;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn api_key_scopes_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/api_keys",
+                &token,
+                json!({"name": "ci", "scopes": ["users:read"]}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/api_keys",
+                &token,
+                json!({"name": "ci", "scopes": ["tasks:read"], "expires_in_days": 30}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let key = response_json(response).await["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(http::Method::GET, "/task", &key, json!({})))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Out of the key's scopes, and account routes are never reachable
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/task",
+                &key,
+                json!({"title": "test title"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::GET,
+                "/api_keys",
+                &key,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::GET,
+                "/api_keys",
+                &token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let api_keys = response_json(response).await;
+        assert!(api_keys[0]["key"].is_null());
+        assert!(!api_keys[0]["last_used_at"].is_null());
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::DELETE,
+                &format!("/api_keys/{}", api_keys[0]["id"]),
+                &token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(http::Method::GET, "/task", &key, json!({})))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
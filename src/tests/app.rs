@@ -1,37 +1,184 @@
+use std::sync::Arc;
+
 use axum::{
-    routing::{get, post},
+    body::Body,
+    http::{self, Request, StatusCode},
+    response::Response,
     Router,
 };
 use dotenvy_macro::dotenv;
-use sea_orm::Database;
+use sea_orm::{prelude::Uuid, Database};
+use serde_json::json;
+use tower::ServiceExt;
 
 use crate::{
-    routes::{
-        index::hello_world,
-        task::{create_task, delete_task, get_all_tasks, get_task, update_task},
-    },
+    config::Config,
+    router::create_routes,
     server::AppState,
+    utils::{
+        authenticator::LocalAuthenticator,
+        jwt::create_token,
+        jwt_keys::JwtKeys,
+        login_throttle::LoginThrottle,
+        mailer::FileMailer,
+        proof_of_work::{pow_hash_zeros, ProofOfWork},
+        roles::Role,
+        webauthn::Webauthn,
+    },
 };
 
-pub async fn app_test() -> Router {
+pub async fn app_state_test() -> AppState {
     let database_uri = dotenv!("DATABASE_URL").to_owned();
-    let jwt_secret = dotenv!("JWT_SECRET").to_owned();
+    let jwt_keys = JwtKeys::from_secret(dotenv!("JWT_SECRET"));
 
     let database_conn = Database::connect(database_uri).await.unwrap();
 
-    let app_state = AppState {
+    let mut config = Config::from_env();
+    // Tests solve a puzzle for each login, keep them cheap
+    config.pow_difficulty = 8;
+
+    AppState {
         database_conn,
-        jwt_secret,
-    };
+        jwt_keys,
+        mailer: Arc::new(mailer_test()),
+        webauthn: Webauthn::new(&config.app_url),
+        authenticator: Arc::new(LocalAuthenticator::new(config.password_hashing)),
+        proof_of_work: ProofOfWork::new(config.pow_difficulty),
+        config,
+        login_throttle: LoginThrottle::default(),
+        oidc: None,
+    }
+}
+
+pub async fn app_test() -> Router {
+    create_routes(app_state_test().await).await
+}
 
-    let guest_nest = Router::new().route("/", get(hello_world));
+/// All test apps share this mailer, usernames are unique so tests only see
+/// their own mails.
+pub fn mailer_test() -> FileMailer {
+    FileMailer::new(std::env::temp_dir().join("axum-webapp-mails"))
+}
+
+/// Token of the last link mailed to `username`.
+pub async fn mailed_token_test(username: &str) -> String {
+    let mails = mailer_test().mails(username).await.unwrap();
+    let mail = mails.last().expect("no mail received");
+
+    mail.split("token=").nth(1).unwrap()[..43].to_string()
+}
+
+pub async fn bearer_test(user_id: i32, role: Role) -> String {
+    let jwt_keys = JwtKeys::from_secret(dotenv!("JWT_SECRET"));
+    let token = create_token(&jwt_keys, user_id, role, None).await.unwrap();
+
+    format!("Bearer {}", token)
+}
+
+pub fn json_request(method: http::Method, uri: &str, body: serde_json::Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .body(Body::from(serde_json::to_vec(&body).unwrap()))
+        .unwrap()
+}
+
+pub async fn response_json(response: Response) -> serde_json::Value {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Registers a new user with a unique username and returns its credentials.
+pub async fn register_unverified_test(app: &Router) -> (String, String) {
+    let username = format!("{}@test.com", Uuid::new_v4());
+    let password = "password1234".to_string();
+
+    let response = app
+        .clone()
+        .oneshot(json_request(
+            http::Method::POST,
+            "/register",
+            json!({"username": username, "password": password, "pow": pow_test(app).await}),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    (username, password)
+}
+
+/// Registers a new user and follows the verification link it was mailed.
+pub async fn register_test(app: &Router) -> (String, String) {
+    let (username, password) = register_unverified_test(app).await;
+
+    let response = app
+        .clone()
+        .oneshot(json_request(
+            http::Method::POST,
+            "/verify_email",
+            json!({ "token": mailed_token_test(&username).await }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    (username, password)
+}
+
+pub fn bearer_request(
+    method: http::Method,
+    uri: &str,
+    token: &str,
+    body: serde_json::Value,
+) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::from(serde_json::to_vec(&body).unwrap()))
+        .unwrap()
+}
+
+/// Fetches a challenge and solves it, for the `pow` field of `/register` and
+/// `/login`.
+pub async fn pow_test(app: &Router) -> serde_json::Value {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/pow/challenge")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+
+    let challenge = body["challenge"].as_str().unwrap();
+    let difficulty = body["difficulty"].as_u64().unwrap() as u32;
+    let solution = (0u64..)
+        .find(|solution| pow_hash_zeros(challenge, &solution.to_string()) >= difficulty)
+        .unwrap();
+
+    json!({ "challenge": challenge, "solution": solution.to_string() })
+}
 
-    let user_nest = Router::new()
-        .route("/", post(create_task).get(get_all_tasks))
-        .route("/:id", get(get_task).put(update_task).delete(delete_task));
+/// Logs in and returns the response body of `/login`.
+pub async fn login_test(app: &Router, username: &str, password: &str) -> serde_json::Value {
+    let response = app
+        .clone()
+        .oneshot(json_request(
+            http::Method::POST,
+            "/login",
+            json!({"username": username, "password": password, "pow": pow_test(app).await}),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
 
-    Router::new()
-        .nest("", guest_nest)
-        .nest("/task", user_nest)
-        .with_state(app_state)
+    response_json(response).await
 }
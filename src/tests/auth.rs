@@ -0,0 +1,344 @@
+#[cfg(test)]
+mod tests {
+    use crate::database::users;
+    use crate::router::create_routes;
+    use crate::tests::app::{
+        app_state_test, app_test, json_request, login_test, pow_test, register_test, response_json,
+    };
+    use axum::body::Body;
+    use axum::http;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+    use axum::response::Response;
+    use chrono::Utc;
+    use sea_orm::{prelude::Uuid, ActiveModelTrait, EntityTrait, Set};
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn renew_auth_reuse_revokes_family_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let first = response_json(response).await["refresh_token"].clone();
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/renew_auth",
+                json!({ "refresh_token": first }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let second = response_json(response).await["refresh_token"].clone();
+
+        // Replaying the rotated token must fail and take the new one with it
+        for refresh_token in [first, second] {
+            let response = app
+                .clone()
+                .oneshot(json_request(
+                    http::Method::POST,
+                    "/renew_auth",
+                    json!({ "refresh_token": refresh_token }),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_token_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        let token = response_json(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let bearer = format!("Bearer {}", token);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/logout")
+                    .header(http::header::AUTHORIZATION, &bearer)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/task")
+                    .header(http::header::AUTHORIZATION, &bearer)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn failed_logins_lock_account_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": format!("unknown-{}", username), "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let unknown_user_body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        for _ in 0..5 {
+            let response = app
+                .clone()
+                .oneshot(json_request(
+                    http::Method::POST,
+                    "/login",
+                    json!({"username": username, "password": "wrong password", "pow": pow_test(&app).await}),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            // Unknown users and wrong passwords can't be told apart
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, unknown_user_body);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    /// `Cookie` header sending back the cookies a response set.
+    fn cookies_of(response: &Response) -> String {
+        response
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .iter()
+            .map(|cookie| cookie.to_str().unwrap().split(';').next().unwrap())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn cookie_request(
+        method: http::Method,
+        uri: &str,
+        cookies: &str,
+        csrf_token: Option<&str>,
+    ) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(http::header::COOKIE, cookies);
+        if let Some(csrf_token) = csrf_token {
+            request = request.header("X-CSRF-Token", csrf_token);
+        }
+
+        request
+            .body(Body::from(
+                serde_json::to_vec(&json!({"title": "test title"})).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cookie_session_requires_csrf_token_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login?session=cookie",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookies = cookies_of(&response);
+        assert!(response
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .iter()
+            .map(|cookie| cookie.to_str().unwrap())
+            .any(|cookie| cookie.starts_with("session=") && cookie.contains("HttpOnly")));
+        let body = response_json(response).await;
+        assert!(body.get("token").is_none());
+        let csrf_token = body["csrf_token"].as_str().unwrap().to_string();
+
+        let response = app
+            .clone()
+            .oneshot(cookie_request(http::Method::GET, "/task", &cookies, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(cookie_request(http::Method::POST, "/task", &cookies, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(cookie_request(
+                http::Method::POST,
+                "/task",
+                &cookies,
+                Some(&csrf_token),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(cookie_request(
+                http::Method::POST,
+                "/renew_auth",
+                &cookies,
+                Some(&csrf_token),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let renewed_cookies = cookies_of(&response);
+        let renewed_csrf_token = response_json(response).await["csrf_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // The CSRF token is bound to the session it was issued with
+        let response = app
+            .clone()
+            .oneshot(cookie_request(
+                http::Method::POST,
+                "/task",
+                &renewed_cookies,
+                Some(&csrf_token),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(cookie_request(
+                http::Method::POST,
+                "/logout",
+                &renewed_cookies,
+                Some(&renewed_csrf_token),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(cookies_of(&response).contains("session="));
+
+        let response = app
+            .clone()
+            .oneshot(cookie_request(
+                http::Method::GET,
+                "/task",
+                &renewed_cookies,
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn legacy_bcrypt_hash_is_upgraded_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+
+        let username = format!("{}@test.com", Uuid::new_v4());
+        let user = users::ActiveModel {
+            username: Set(username.clone()),
+            password: Set(bcrypt::hash("password1234", 4).unwrap()),
+            verified_at: Set(Some(Utc::now().into())),
+            ..Default::default()
+        }
+        .insert(&database_conn)
+        .await
+        .unwrap();
+
+        login_test(&app, &username, "password1234").await;
+
+        let user = users::Entity::find_by_id(user.id)
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.password.starts_with("$argon2id$"));
+
+        login_test(&app, &username, "password1234").await;
+    }
+
+    #[tokio::test]
+    async fn short_password_login_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+
+        // The policy decides on new passwords, logins take any length
+        let username = format!("{}@test.com", Uuid::new_v4());
+        users::ActiveModel {
+            username: Set(username.clone()),
+            password: Set(bcrypt::hash("short", 4).unwrap()),
+            verified_at: Set(Some(Utc::now().into())),
+            ..Default::default()
+        }
+        .insert(&database_conn)
+        .await
+        .unwrap();
+
+        login_test(&app, &username, "short").await;
+    }
+}
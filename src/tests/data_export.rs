@@ -0,0 +1,142 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{app_test, bearer_request, bearer_test, login_test, register_test};
+    use crate::utils::audit::DATA_EXPORTED;
+    use crate::utils::roles::Role;
+    use async_zip::base::read::mem::ZipFileReader;
+    use axum::http;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    /// Files of the zip, each checked against the CRC of its entry.
+    async fn unzip(archive: &[u8]) -> HashMap<String, serde_json::Value> {
+        let archive = ZipFileReader::new(archive.to_vec()).await.unwrap();
+
+        let mut files = HashMap::new();
+        for index in 0..archive.file().entries().len() {
+            let mut entry = archive.reader_with_entry(index).await.unwrap();
+            let mut content = Vec::new();
+            entry.read_to_end_checked(&mut content).await.unwrap();
+            files.insert(
+                entry.entry().filename().as_str().unwrap().to_string(),
+                serde_json::from_slice(&content).unwrap(),
+            );
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn data_export_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        for title in ["first", "second"] {
+            let response = app
+                .clone()
+                .oneshot(bearer_request(
+                    http::Method::POST,
+                    "/task",
+                    &token,
+                    json!({ "title": title }),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/api_keys",
+                &token,
+                json!({"name": "ci", "scopes": ["tasks:read"]}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::GET,
+                "/me/export",
+                &token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let export: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(export["user"]["username"], json!(username));
+        assert!(export["user"].get("password").is_none());
+        assert!(export["user"].get("totp_secret").is_none());
+        let titles: Vec<&str> = export["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|task| task["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["first", "second"]);
+        assert_eq!(export["sessions"].as_array().unwrap().len(), 1);
+        assert_eq!(export["api_keys"][0]["name"], json!("ci"));
+        assert_eq!(export["api_keys"][0]["scopes"], json!(["tasks:read"]));
+        assert!(export["api_keys"][0].get("key_hash").is_none());
+        for section in ["webauthn_credentials", "identities", "recovery_codes"] {
+            assert_eq!(export[section], json!([]));
+        }
+        assert_eq!(export["audit_events"][0]["action"], json!(DATA_EXPORTED));
+
+        // Admins get the same data, here as one file per section
+        let admin = bearer_test(1, Role::Admin).await;
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::GET,
+                &format!("/user/{}/export?format=zip", username),
+                admin.trim_start_matches("Bearer "),
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/zip"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let files = unzip(&body).await;
+
+        assert_eq!(files.len(), 8);
+        assert_eq!(files["user.json"], export["user"]);
+        assert_eq!(files["tasks.json"], export["tasks"]);
+        assert_eq!(files["api_keys.json"], export["api_keys"]);
+        let events = files["audit_events.json"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["actor_id"], json!(1));
+
+        // Nobody else's data can be exported by a user
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::GET,
+                &format!("/user/{}/export", username),
+                &token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
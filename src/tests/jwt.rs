@@ -0,0 +1,41 @@
+#[cfg(test)]
+mod tests {
+    use crate::utils::jwt::{create_token, decode_token};
+    use crate::utils::jwt_keys::JwtKeys;
+    use crate::utils::roles::Role;
+    use ring::rand::SystemRandom;
+    use ring::signature::Ed25519KeyPair;
+
+    fn ed25519_pem() -> String {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+
+        pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_string(),
+            contents: pkcs8.as_ref().to_vec(),
+        })
+    }
+
+    #[tokio::test]
+    async fn rotated_key_still_verifies_test() {
+        let pems = vec![
+            ("old".to_string(), ed25519_pem()),
+            ("new".to_string(), ed25519_pem()),
+        ];
+        let old_keys = JwtKeys::from_pems(pems.clone(), "old").unwrap();
+        let new_keys = JwtKeys::from_pems(pems, "new").unwrap();
+
+        let token = create_token(&old_keys, 1, Role::User, None).await.unwrap();
+        let claims = decode_token(&new_keys, token).await.unwrap();
+        assert_eq!(claims.id, 1);
+
+        let jwks = serde_json::to_value(new_keys.jwks()).unwrap();
+        assert_eq!(jwks["keys"].as_array().unwrap().len(), 2);
+        assert_eq!(jwks["keys"][0]["kty"], "OKP");
+        assert!(jwks["keys"][0].get("d").is_none());
+
+        let forged = create_token(&JwtKeys::from_secret("secret"), 1, Role::Admin, None)
+            .await
+            .unwrap();
+        assert!(decode_token(&new_keys, forged).await.is_err());
+    }
+}
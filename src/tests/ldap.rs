@@ -0,0 +1,322 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::config::LdapConfig;
+    use crate::database::{user_identities, users};
+    use crate::router::create_routes;
+    use crate::server::AppState;
+    use crate::tests::app::{
+        app_state_test, app_test, bearer_request, bearer_test, json_request, login_test, pow_test,
+        register_test,
+    };
+    use crate::utils::authenticator::{Authenticator, ChainedAuthenticator, LocalAuthenticator};
+    use crate::utils::ldap::LdapAuthenticator;
+    use crate::utils::roles::Role;
+    use axum::http;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use ldap3::asn1::{parse_tag, StructureTag, TagClass, PL};
+    use sea_orm::prelude::Uuid;
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    const ADMINS_GROUP: &str = "cn=admins,ou=groups,dc=example,dc=org";
+
+    struct DirectoryEntry {
+        password: String,
+        groups: Vec<String>,
+    }
+
+    type Directory = Arc<HashMap<String, DirectoryEntry>>;
+
+    /// BER type, length and value, with the short or long length form.
+    fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        match value.len() {
+            length @ 0..=127 => encoded.push(length as u8),
+            length @ 128..=255 => encoded.extend_from_slice(&[0x81, length as u8]),
+            length => {
+                encoded.push(0x82);
+                encoded.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        encoded.extend_from_slice(value);
+        encoded
+    }
+
+    /// LDAPResult of a BindResponse or SearchResultDone.
+    fn ldap_result(tag: u8, result_code: u8) -> Vec<u8> {
+        let mut value = tlv(0x0a, &[result_code]);
+        value.extend(tlv(0x04, b""));
+        value.extend(tlv(0x04, b""));
+        tlv(tag, &value)
+    }
+
+    fn search_result_entry(dn: &str, groups: &[String]) -> Vec<u8> {
+        let values: Vec<u8> = groups
+            .iter()
+            .flat_map(|group| tlv(0x04, group.as_bytes()))
+            .collect();
+        let mut attribute = tlv(0x04, b"memberOf");
+        attribute.extend(tlv(0x31, &values));
+
+        let mut value = tlv(0x04, dn.as_bytes());
+        value.extend(tlv(0x30, &tlv(0x30, &attribute)));
+        tlv(0x64, &value)
+    }
+
+    fn primitive(tag: StructureTag) -> String {
+        String::from_utf8(tag.expect_primitive().unwrap()).unwrap()
+    }
+
+    /// Answers the requests of one LDAPMessage, `None` once the client unbinds.
+    fn answer(
+        directory: &Directory,
+        bound_dn: &mut Option<String>,
+        message: StructureTag,
+    ) -> Option<Vec<u8>> {
+        let mut fields = message.expect_constructed().unwrap().into_iter();
+        let message_id = tlv(0x02, &fields.next().unwrap().expect_primitive().unwrap());
+        let operation = fields.next().unwrap();
+        assert_eq!(operation.class, TagClass::Application);
+
+        let responses = match operation.id {
+            // BindRequest: version, name and simple password
+            0 => {
+                let mut fields = operation.expect_constructed().unwrap().into_iter().skip(1);
+                let dn = primitive(fields.next().unwrap());
+                let password = primitive(fields.next().unwrap());
+
+                let is_valid = directory
+                    .get(&dn)
+                    .is_some_and(|entry| entry.password == password);
+                *bound_dn = is_valid.then_some(dn);
+
+                vec![ldap_result(0x61, if is_valid { 0 } else { 49 })]
+            }
+            // SearchRequest, only the bound entry can be read
+            3 => {
+                let base = primitive(operation.expect_constructed().unwrap().remove(0));
+                let mut responses = Vec::new();
+                if bound_dn.as_ref() == Some(&base) {
+                    responses.push(search_result_entry(&base, &directory[&base].groups));
+                }
+                responses.push(ldap_result(0x65, 0));
+                responses
+            }
+            // UnbindRequest
+            2 => return None,
+            id => panic!("Unexpected LDAP operation {}", id),
+        };
+
+        Some(
+            responses
+                .into_iter()
+                .flat_map(|response| {
+                    let mut value = message_id.clone();
+                    value.extend(response);
+                    tlv(0x30, &value)
+                })
+                .collect(),
+        )
+    }
+
+    async fn serve_connection(directory: Directory, mut stream: TcpStream) {
+        let mut buffer = Vec::new();
+        let mut bound_dn = None;
+
+        loop {
+            let mut chunk = [0; 4096];
+            let read = stream.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                return;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+
+            // Messages may arrive split or several at once
+            while let Ok((rest, message)) = parse_tag(&buffer) {
+                buffer = rest.to_vec();
+                assert!(matches!(message.payload, PL::C(_)));
+
+                match answer(&directory, &mut bound_dn, message) {
+                    Some(responses) => stream.write_all(&responses).await.unwrap(),
+                    None => return,
+                }
+            }
+        }
+    }
+
+    /// In-process directory server, knowing just enough of LDAPv3 for simple
+    /// binds and base searches. Returns its URL.
+    async fn ldap_server_test(directory: HashMap<String, DirectoryEntry>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let directory = Arc::new(directory);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve_connection(directory.clone(), stream));
+            }
+        });
+
+        format!("ldap://{}", address)
+    }
+
+    /// App state checking passwords against the directory at `url`, then
+    /// against the local accounts.
+    async fn ldap_app_state_test(url: String) -> AppState {
+        let mut app_state = app_state_test().await;
+        let ldap = LdapAuthenticator::new(
+            LdapConfig {
+                url,
+                dn_template: "uid={username},ou=people,dc=example,dc=org".to_string(),
+                role_groups: vec![(ADMINS_GROUP.to_string(), Role::Admin)],
+            },
+            app_state.config.password_hashing,
+        );
+        let local: Arc<dyn Authenticator> =
+            Arc::new(LocalAuthenticator::new(app_state.config.password_hashing));
+        app_state.authenticator = Arc::new(ChainedAuthenticator(vec![Arc::new(ldap), local]));
+        app_state
+    }
+
+    async fn login_status(app: &Router, username: &str, password: &str) -> StatusCode {
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({
+                    "username": username,
+                    "password": password,
+                    "pow": pow_test(app).await,
+                }),
+            ))
+            .await
+            .unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn ldap_login_test() {
+        let username = format!("{}@example.org", Uuid::new_v4());
+        let password = "directory password";
+
+        let mut directory = HashMap::new();
+        directory.insert(
+            format!("uid={},ou=people,dc=example,dc=org", username),
+            DirectoryEntry {
+                password: password.to_string(),
+                groups: vec![ADMINS_GROUP.to_string()],
+            },
+        );
+        let url = ldap_server_test(directory).await;
+
+        let app_state = ldap_app_state_test(url).await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+
+        // The first login creates the user, with the role of its groups
+        let body = login_test(&app, &username, password).await;
+        assert!(body["token"].is_string());
+
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username.clone()))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.role, Role::Admin.as_str());
+        assert!(user.verified_at.is_some());
+
+        // Its groups keep deciding
+        let admin = bearer_test(1, Role::Admin).await;
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::PUT,
+                &format!("/user/{}/role", username),
+                admin.trim_start_matches("Bearer "),
+                json!({ "role": "user" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        login_test(&app, &username, password).await;
+
+        assert_eq!(
+            login_status(&app, &username, "wrong password").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Local accounts are still checked after the directory
+        let (username, password) = register_test(&app).await;
+        login_test(&app, &username, &password).await;
+    }
+
+    #[tokio::test]
+    async fn ldap_login_refuses_local_account_test() {
+        let (username, local_password) = register_test(&app_test().await).await;
+
+        // The directory has an admin entry of the same name
+        let mut directory = HashMap::new();
+        directory.insert(
+            format!("uid={},ou=people,dc=example,dc=org", username),
+            DirectoryEntry {
+                password: "directory password".to_string(),
+                groups: vec![ADMINS_GROUP.to_string()],
+            },
+        );
+        let url = ldap_server_test(directory).await;
+
+        let app_state = ldap_app_state_test(url).await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+
+        assert_eq!(
+            login_status(&app, &username, "directory password").await,
+            StatusCode::UNAUTHORIZED
+        );
+        login_test(&app, &username, &local_password).await;
+
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.role, Role::User.as_str());
+        let identities = user_identities::Entity::find()
+            .filter(user_identities::Column::UserId.eq(user.id))
+            .all(&database_conn)
+            .await
+            .unwrap();
+        assert!(identities.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ldap_outage_keeps_local_logins_test() {
+        // Nothing listens on the port of a dropped listener
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let app = create_routes(ldap_app_state_test(url).await).await;
+
+        let (username, password) = register_test(&app).await;
+        login_test(&app, &username, &password).await;
+
+        // Without a local account, the directory error is what's left
+        assert_eq!(
+            login_status(&app, "nobody@example.org", "password1234").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
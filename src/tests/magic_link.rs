@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{
+        app_test, json_request, mailed_token_test, mailer_test, register_test, response_json,
+    };
+    use axum::http;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn magic_link_login_test() {
+        let app = app_test().await;
+        let (username, _) = register_test(&app).await;
+
+        // The second request comes too soon and isn't mailed
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(json_request(
+                    http::Method::POST,
+                    "/magic_link",
+                    json!({ "username": username }),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let mails = mailer_test().mails(&username).await.unwrap();
+        let login_mails = mails
+            .iter()
+            .filter(|mail| mail.contains("/magic_link?token="))
+            .count();
+        assert_eq!(login_mails, 1);
+
+        let token = mailed_token_test(&username).await;
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/magic_link/login",
+                json!({ "token": token }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response_json(response).await["token"].is_string());
+
+        // The link can't be replayed
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/magic_link/login",
+                json!({ "token": token }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
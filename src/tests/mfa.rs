@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{
+        app_test, bearer_request, json_request, login_test, register_test, response_json,
+    };
+    use crate::utils::totp::current_code;
+    use axum::http;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn totp_login_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/mfa/totp/enroll",
+                &token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let secret = response_json(response).await["secret"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/mfa/totp/confirm",
+                &token,
+                json!({ "code": current_code(&secret, &username).unwrap() }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let recovery_code = response_json(response).await["recovery_codes"][0].clone();
+
+        let login = login_test(&app, &username, &password).await;
+        assert!(login.get("token").is_none());
+        let mfa_token = login["mfa_token"].clone();
+
+        for (code, status) in [
+            (json!("000000"), StatusCode::UNAUTHORIZED),
+            (recovery_code.clone(), StatusCode::OK),
+            (recovery_code, StatusCode::UNAUTHORIZED),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(json_request(
+                    http::Method::POST,
+                    "/login/mfa",
+                    json!({"mfa_token": mfa_token, "code": code}),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+}
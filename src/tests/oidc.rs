@@ -0,0 +1,261 @@
+#[cfg(test)]
+mod tests {
+    use crate::config::OidcConfig;
+    use crate::database::user_identities;
+    use crate::router::create_routes;
+    use crate::server::AppState;
+    use crate::tests::app::{app_state_test, json_request, pow_test};
+    use crate::utils::jwt_keys::JwtKeys;
+    use crate::utils::oidc::OidcClient;
+    use axum::body::Body;
+    use axum::extract::{Form, Query, State};
+    use axum::http;
+    use axum::http::{Request, StatusCode};
+    use axum::response::{IntoResponse, Redirect, Response};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::Utc;
+    use jsonwebtoken::{encode, Header};
+    use reqwest::Url;
+    use ring::rand::SystemRandom;
+    use ring::signature::Ed25519KeyPair;
+    use sea_orm::{prelude::Uuid, ColumnTrait, EntityTrait, QueryFilter};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    const CLIENT_ID: &str = "axum-webapp";
+
+    /// Identity provider logging everyone in as the same user.
+    #[derive(Clone)]
+    struct MockIdp {
+        issuer: String,
+        keys: JwtKeys,
+        email: String,
+        /// Issued codes with the nonce and code challenge of their login
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    async fn discovery(State(idp): State<MockIdp>) -> Json<serde_json::Value> {
+        Json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+    }
+
+    async fn authorize(
+        State(idp): State<MockIdp>,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> Redirect {
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        let code = Uuid::new_v4().to_string();
+        idp.codes.lock().unwrap().insert(
+            code.clone(),
+            (params["nonce"].clone(), params["code_challenge"].clone()),
+        );
+
+        Redirect::to(&format!(
+            "{}?code={}&state={}",
+            params["redirect_uri"], code, params["state"]
+        ))
+    }
+
+    async fn token(
+        State(idp): State<MockIdp>,
+        Form(params): Form<HashMap<String, String>>,
+    ) -> Response {
+        let (nonce, code_challenge) = match idp.codes.lock().unwrap().remove(&params["code"]) {
+            Some(login) => login,
+            None => return StatusCode::BAD_REQUEST.into_response(),
+        };
+
+        let verifier_hash =
+            URL_SAFE_NO_PAD.encode(Sha256::digest(params["code_verifier"].as_bytes()));
+        if verifier_hash != code_challenge || params["client_id"] != CLIENT_ID {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+
+        let mut header = Header::new(idp.keys.algorithm());
+        header.kid = idp.keys.active_kid();
+        let claims = json!({
+            "iss": idp.issuer,
+            "aud": CLIENT_ID,
+            "sub": format!("sub-{}", idp.email),
+            "email": idp.email,
+            "email_verified": true,
+            "nonce": nonce,
+            "exp": Utc::now().timestamp() + 300,
+        });
+        let id_token = encode(&header, &claims, idp.keys.encoding_key()).unwrap();
+
+        Json(json!({ "access_token": "unused", "token_type": "Bearer", "id_token": id_token }))
+            .into_response()
+    }
+
+    async fn jwks(State(idp): State<MockIdp>) -> Response {
+        Json(idp.keys.jwks()).into_response()
+    }
+
+    /// Starts the provider on a random port and returns its issuer.
+    fn mock_idp(email: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pem = pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_string(),
+            contents: pkcs8.as_ref().to_vec(),
+        });
+
+        let idp = MockIdp {
+            issuer: issuer.clone(),
+            keys: JwtKeys::from_pems(vec![("idp".to_string(), pem)], "idp").unwrap(),
+            email: email.to_string(),
+            codes: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .route("/jwks", get(jwks))
+            .with_state(idp);
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        issuer
+    }
+
+    fn get_request(uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(http::Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn redirect_location(response: &Response) -> Url {
+        Url::parse(response.headers()[http::header::LOCATION].to_str().unwrap()).unwrap()
+    }
+
+    /// App state logging in through a provider that knows `email`.
+    async fn oidc_app_state_test(email: &str) -> AppState {
+        let issuer = mock_idp(email);
+
+        let mut app_state = app_state_test().await;
+        app_state.oidc = Some(OidcClient::new(
+            OidcConfig {
+                issuer,
+                client_id: CLIENT_ID.to_string(),
+                client_secret: Some("secret".to_string()),
+            },
+            &app_state.config.app_url,
+        ));
+        app_state
+    }
+
+    /// Goes through the provider and returns the callback it redirected to.
+    async fn oidc_callback_test(app: &Router) -> String {
+        let response = app
+            .clone()
+            .oneshot(get_request("/oidc/login"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let authorization_url = redirect_location(&response);
+
+        // The user logs in at the provider, which redirects back
+        let response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(authorization_url)
+            .send()
+            .await
+            .unwrap();
+        let callback = Url::parse(
+            response.headers()[reqwest::header::LOCATION]
+                .to_str()
+                .unwrap(),
+        )
+        .unwrap();
+
+        format!("{}?{}", callback.path(), callback.query().unwrap())
+    }
+
+    #[tokio::test]
+    async fn oidc_login_test() {
+        let email = format!("{}@test.com", Uuid::new_v4());
+        let app_state = oidc_app_state_test(&email).await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+
+        let mut callbacks = Vec::new();
+        for _ in 0..2 {
+            let callback = oidc_callback_test(&app).await;
+            let response = app.clone().oneshot(get_request(&callback)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            callbacks.push(callback);
+        }
+
+        // Both logins used the same account, and a login can't be replayed
+        let identities = user_identities::Entity::find()
+            .filter(user_identities::Column::Subject.eq(format!("sub-{}", email)))
+            .all(&database_conn)
+            .await
+            .unwrap();
+        assert_eq!(identities.len(), 1);
+
+        let response = app
+            .clone()
+            .oneshot(get_request(&callbacks[0]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn oidc_login_takes_over_unverified_account_test() {
+        let email = format!("{}@test.com", Uuid::new_v4());
+        let app = create_routes(oidc_app_state_test(&email).await).await;
+
+        // Someone registers the address before its owner, with their password
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/register",
+                json!({"username": email, "password": "attacker password", "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let callback = oidc_callback_test(&app).await;
+        let response = app.clone().oneshot(get_request(&callback)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The account is verified now, but not with the password set before
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": email, "password": "attacker password", "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{app_test, json_request, mailed_token_test, pow_test, register_test};
+    use axum::http;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn password_reset_test() {
+        let app = app_test().await;
+        let (username, _) = register_test(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/password_reset",
+                json!({ "username": username }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let token = mailed_token_test(&username).await;
+        let confirm = json!({"token": token, "password": "new password"});
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/password_reset/confirm",
+                confirm.clone(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Reset tokens are single-use
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/password_reset/confirm",
+                confirm,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let pow = pow_test(&app).await;
+        let response = app
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": "new password", "pow": pow}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{app_test, json_request, pow_test};
+    use crate::utils::jwt_keys::JwtKeys;
+    use crate::utils::proof_of_work::{pow_hash_zeros, ProofOfWork};
+    use axum::http;
+    use axum::http::StatusCode;
+    use dotenvy_macro::dotenv;
+    use sea_orm::prelude::Uuid;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn register_requires_proof_of_work_test() {
+        let app = app_test().await;
+        let register = |pow| {
+            json_request(
+                http::Method::POST,
+                "/register",
+                json!({
+                    "username": format!("{}@test.com", Uuid::new_v4()),
+                    "password": "password1234",
+                    "pow": pow,
+                }),
+            )
+        };
+
+        let mut pow = pow_test(&app).await;
+        let challenge = pow["challenge"].as_str().unwrap().to_string();
+        let solution = pow["solution"].as_str().unwrap().to_string();
+
+        let wrong_solution = (0u64..)
+            .map(|guess| guess.to_string())
+            .find(|guess| pow_hash_zeros(&challenge, guess) == 0)
+            .unwrap();
+        pow["solution"] = json!(wrong_solution);
+        let response = app.clone().oneshot(register(pow.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        pow["solution"] = json!(solution);
+        let response = app.clone().oneshot(register(pow.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A solution is only good for one request
+        let response = app.clone().oneshot(register(pow)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn difficulty_rises_with_volume_test() {
+        let jwt_keys = JwtKeys::from_secret(dotenv!("JWT_SECRET"));
+        let proof_of_work = ProofOfWork::new(8);
+
+        let (_, difficulty) = proof_of_work.issue(&jwt_keys).await.unwrap();
+        assert_eq!(difficulty, 8);
+
+        for _ in 0..58 {
+            proof_of_work.issue(&jwt_keys).await.unwrap();
+        }
+        let (_, difficulty) = proof_of_work.issue(&jwt_keys).await.unwrap();
+        assert_eq!(difficulty, 9);
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{
+        app_test, bearer_request, json_request, pow_test, register_test, response_json,
+    };
+    use axum::body::Body;
+    use axum::http;
+    use axum::http::{Request, StatusCode};
+    use axum::Router;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    async fn login_from(
+        app: &Router,
+        username: &str,
+        password: &str,
+        user_agent: &str,
+    ) -> serde_json::Value {
+        let mut request = json_request(
+            http::Method::POST,
+            "/login",
+            json!({"username": username, "password": password, "pow": pow_test(app).await}),
+        );
+        request
+            .headers_mut()
+            .insert(http::header::USER_AGENT, user_agent.parse().unwrap());
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        response_json(response).await
+    }
+
+    fn get_request(uri: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .method(http::Method::GET)
+            .uri(uri)
+            .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn revoke_session_test() {
+        let app = app_test().await;
+        let (username, password) = register_test(&app).await;
+
+        let laptop = login_from(&app, &username, &password, "Laptop").await;
+        let phone = login_from(&app, &username, &password, "Phone").await;
+        let laptop_token = laptop["token"].as_str().unwrap();
+        let phone_token = phone["token"].as_str().unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/me/sessions", laptop_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let sessions = response_json(response).await;
+        let sessions = sessions.as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+
+        let current = sessions.iter().find(|session| session["current"] == true);
+        assert_eq!(current.unwrap()["user_agent"], "Laptop");
+        let phone_session = sessions
+            .iter()
+            .find(|session| session["user_agent"] == "Phone")
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::DELETE,
+                &format!("/me/sessions/{}", phone_session["id"]),
+                laptop_token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The lost phone can neither use its token nor renew it
+        let response = app
+            .clone()
+            .oneshot(get_request("/task", phone_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/renew_auth",
+                json!({"refresh_token": phone["refresh_token"]}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // The laptop keeps working and is the only session left
+        let response = app
+            .clone()
+            .oneshot(get_request("/me/sessions", laptop_token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response_json(response).await.as_array().unwrap().len(), 1);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::DELETE,
+                &format!("/me/sessions/{}", phone_session["id"]),
+                laptop_token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,6 +1,9 @@
 #[cfg(test)]
 mod tests {
-    use crate::tests::app::app_test;
+    use crate::tests::app::{
+        app_test, bearer_request, bearer_test, login_test, register_test, response_json,
+    };
+    use crate::utils::roles::Role;
     use axum::body::Body;
     use axum::http;
     use axum::http::Request;
@@ -18,6 +21,7 @@ mod tests {
                     .method(http::Method::POST)
                     .uri("/task")
                     .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .header(http::header::AUTHORIZATION, bearer_test(1, Role::User).await)
                     .body(Body::from(
                         serde_json::to_vec(&json!({"title": "test title", "description": "test description", "priority": "qos"})).unwrap(),
                     ))
@@ -28,4 +32,95 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
     }
+
+    #[tokio::test]
+    async fn create_task_without_token_test() {
+        let app = app_test().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/task")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(
+                        serde_json::to_vec(&json!({"title": "test title"})).unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn task_owner_test() {
+        let app = app_test().await;
+        let mut tokens = Vec::new();
+        for _ in 0..2 {
+            let (username, password) = register_test(&app).await;
+            let token = login_test(&app, &username, &password).await["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            tokens.push(token);
+        }
+        let (owner, other) = (&tokens[0], &tokens[1]);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/task",
+                owner,
+                json!({"title": "mine"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(http::Method::GET, "/task", owner, json!({})))
+            .await
+            .unwrap();
+        let tasks = response_json(response).await;
+        assert_eq!(tasks.as_array().unwrap().len(), 1);
+        let uri = format!("/task/{}", tasks[0]["id"]);
+
+        // Other users neither see the task nor can change it
+        let response = app
+            .clone()
+            .oneshot(bearer_request(http::Method::GET, "/task", other, json!({})))
+            .await
+            .unwrap();
+        assert_eq!(response_json(response).await, json!([]));
+        for method in [http::Method::GET, http::Method::DELETE] {
+            let response = app
+                .clone()
+                .oneshot(bearer_request(method, &uri, other, json!({})))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::PUT,
+                &uri,
+                other,
+                json!({"title": "theirs"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(http::Method::DELETE, &uri, owner, json!({})))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 }
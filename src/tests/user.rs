@@ -0,0 +1,741 @@
+#[cfg(test)]
+mod tests {
+    use crate::database::{audit_events, tasks, users};
+    use crate::router::create_routes;
+    use crate::tests::app::{
+        app_state_test, app_test, bearer_request, bearer_test, json_request, login_test,
+        mailed_token_test, pow_test, register_test, register_unverified_test, response_json,
+    };
+    use crate::utils::audit::{
+        PASSWORD_RESET_FORCED, ROLE_CHANGED, USER_DELETED, USER_DISABLED, USER_ENABLED,
+        USER_PURGED, USER_RESTORED,
+    };
+    use crate::utils::password_policy::{CharacterClass, PasswordPolicy};
+    use crate::utils::refresh_token::issue_refresh_token;
+    use crate::utils::roles::Role;
+    use crate::utils::user_purge::purge_deleted_users;
+    use axum::body::Body;
+    use axum::http;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use chrono::{Duration, Utc};
+    use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+    use serde_json::json;
+    use sha1::{Digest, Sha1};
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn get_all_users_as_user_test() {
+        let app = app_test().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/user")
+                    .header(
+                        http::header::AUTHORIZATION,
+                        bearer_test(1, Role::User).await,
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn get_all_users_as_admin_test() {
+        let app = app_test().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/user")
+                    .header(
+                        http::header::AUTHORIZATION,
+                        bearer_test(1, Role::Admin).await,
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn register_enforces_password_policy_test() {
+        let breached_passwords_dir = std::env::temp_dir().join("axum-webapp-breached");
+        std::fs::create_dir_all(&breached_passwords_dir).unwrap();
+        let hash: String = Sha1::digest(b"breached password 1")
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        std::fs::write(
+            breached_passwords_dir.join(format!("{}.txt", &hash[..5])),
+            format!("{}:42\n", &hash[5..]),
+        )
+        .unwrap();
+
+        let mut app_state = app_state_test().await;
+        app_state.config.password_policy = PasswordPolicy {
+            min_length: 10,
+            required_classes: vec![CharacterClass::Digit],
+            breached_passwords_dir: Some(breached_passwords_dir),
+        };
+        let app = create_routes(app_state).await;
+
+        let username = format!("{}@test.com", Uuid::new_v4());
+        for (password, error) in [
+            ("short1", "Password must have at least 10 characters"),
+            ("no digits at all", "Password must contain a digit"),
+            (username.as_str(), "Password must not be the username"),
+            (
+                "breached password 1",
+                "Password appears in a list of breached passwords",
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(json_request(
+                    http::Method::POST,
+                    "/register",
+                    json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, error);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/register",
+                json!({
+                    "username": username,
+                    "password": "good password 1",
+                    "pow": pow_test(&app).await,
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn impersonate_user_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+        let (username, _) = register_test(&app).await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri(format!("/user/{}/impersonate", username))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        bearer_test(1, Role::Admin).await,
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = response_json(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/task",
+                &token,
+                json!({"title": "reproduced"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Neither the account nor the administration can be reached
+        for (method, uri) in [
+            (http::Method::GET, "/user"),
+            (http::Method::POST, "/api_keys"),
+            (http::Method::GET, "/me/sessions"),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(bearer_request(method, uri, &token, json!({})))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        let events = audit_events::Entity::find()
+            .filter(audit_events::Column::UserId.eq(user.id))
+            .all(&database_conn)
+            .await
+            .unwrap();
+        assert!(events.iter().all(|event| event.actor_id == Some(1)));
+        assert!(events
+            .iter()
+            .any(|event| event.details.as_deref() == Some("POST /task")));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn profile_and_password_change_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+        let (username, password) = register_test(&app).await;
+        let token = |body: serde_json::Value| body["token"].as_str().unwrap().to_string();
+        let current_login = login_test(&app, &username, &password).await;
+        let current = token(current_login.clone());
+        let other_login = login_test(&app, &username, &password).await;
+        let other = token(other_login.clone());
+
+        let send = |method: http::Method, uri: &str, token: &str, body| {
+            app.clone()
+                .oneshot(bearer_request(method, uri, token, body))
+        };
+
+        let response = send(http::Method::GET, "/me", &current, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let profile = response_json(response).await;
+        assert_eq!(profile["username"], json!(username));
+        assert!(profile.get("password").is_none());
+
+        let response = send(
+            http::Method::PATCH,
+            "/me",
+            &current,
+            json!({ "display_name": "Ada", "timezone": "Europe/Paris", "locale": "fr-FR" }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let profile = response_json(response).await;
+        assert_eq!(profile["display_name"], json!("Ada"));
+        assert_eq!(profile["timezone"], json!("Europe/Paris"));
+        assert_eq!(profile["locale"], json!("fr-FR"));
+
+        // Missing fields are kept, empty ones cleared
+        let response = send(
+            http::Method::PATCH,
+            "/me",
+            &current,
+            json!({ "locale": "" }),
+        )
+        .await
+        .unwrap();
+        let profile = response_json(response).await;
+        assert_eq!(profile["display_name"], json!("Ada"));
+        assert_eq!(profile["locale"], json!(null));
+
+        let response = send(
+            http::Method::PATCH,
+            "/me",
+            &current,
+            json!({ "locale": "not a locale" }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = send(
+            http::Method::POST,
+            "/me/password",
+            &current,
+            json!({ "current_password": "wrong password", "new_password": "new password 1" }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Tokens issued without a session must not outlive the password either
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username.clone()))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        let sessionless_refresh_token =
+            issue_refresh_token(&database_conn, user.id, Uuid::new_v4())
+                .await
+                .unwrap();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri(format!("/user/{}/impersonate", username))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        bearer_test(1, Role::Admin).await,
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let impersonation = token(response_json(response).await);
+
+        let response = send(
+            http::Method::POST,
+            "/me/password",
+            &current,
+            json!({ "current_password": password, "new_password": "new password 1" }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let renew = |refresh_token: &serde_json::Value| {
+            app.clone().oneshot(json_request(
+                http::Method::POST,
+                "/renew_auth",
+                json!({ "refresh_token": refresh_token }),
+            ))
+        };
+        for refresh_token in [
+            &other_login["refresh_token"],
+            &json!(sessionless_refresh_token),
+        ] {
+            let response = renew(refresh_token).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = renew(&current_login["refresh_token"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(http::Method::GET, "/task", &impersonation, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Only the session the password was changed from is still open
+        let response = send(http::Method::GET, "/me", &current, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(http::Method::GET, "/me", &other, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        login_test(&app, &username, "new password 1").await;
+    }
+
+    #[tokio::test]
+    async fn soft_delete_and_restore_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+        let (username, password) = register_test(&app).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username.clone()))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Hard deletes used to fail on the foreign key of the tasks
+        tasks::ActiveModel {
+            title: Set("kept".to_string()),
+            user_id: Set(Some(user.id)),
+            ..Default::default()
+        }
+        .insert(&database_conn)
+        .await
+        .unwrap();
+
+        let admin_request = |method: http::Method, uri: String| async move {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(
+                    http::header::AUTHORIZATION,
+                    bearer_test(1, Role::Admin).await,
+                )
+                .body(Body::empty())
+                .unwrap()
+        };
+        let delete_uri = format!("/user/{}", username);
+
+        let request = admin_request(http::Method::DELETE, delete_uri.clone()).await;
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = admin_request(http::Method::DELETE, delete_uri.clone()).await;
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(http::Method::GET, "/me", &token, json!({})))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let request = admin_request(http::Method::GET, "/user".to_string()).await;
+        let response = app.clone().oneshot(request).await.unwrap();
+        let users = response_json(response).await;
+        assert!(users["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|user| user["username"] != json!(username)));
+
+        let request = admin_request(http::Method::POST, format!("{}/restore", delete_uri)).await;
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        login_test(&app, &username, &password).await;
+
+        // Past the retention window the account is gone for good
+        let request = admin_request(http::Method::DELETE, delete_uri).await;
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let user = users::Entity::find_by_id(user.id)
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        let user_id = user.id;
+        let mut user: users::ActiveModel = user.into();
+        user.deleted_at = Set(Some((Utc::now() - Duration::days(31)).into()));
+        user.update(&database_conn).await.unwrap();
+
+        assert!(purge_deleted_users(&database_conn, 30).await.unwrap() >= 1);
+        assert!(users::Entity::find_by_id(user_id)
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        let actions: Vec<String> = audit_events::Entity::find()
+            .filter(audit_events::Column::UserId.eq(user_id))
+            .all(&database_conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(
+            actions,
+            [USER_DELETED, USER_RESTORED, USER_DELETED, USER_PURGED]
+        );
+    }
+
+    /// Admin listing of `/user` with the query string.
+    async fn list_users(app: &Router, query: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/user?{}", query))
+                    .header(
+                        http::header::AUTHORIZATION,
+                        bearer_test(1, Role::Admin).await,
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+
+        if status == StatusCode::OK {
+            (status, response_json(response).await)
+        } else {
+            (status, serde_json::Value::Null)
+        }
+    }
+
+    #[tokio::test]
+    async fn list_users_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+        let (username, _) = register_test(&app).await;
+        let (unverified, _) = register_unverified_test(&app).await;
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username.clone()))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Deleted tasks aren't counted
+        for deleted_at in [None, None, Some(Utc::now().into())] {
+            tasks::ActiveModel {
+                title: Set("owned".to_string()),
+                user_id: Set(Some(user.id)),
+                deleted_at: Set(deleted_at),
+                ..Default::default()
+            }
+            .insert(&database_conn)
+            .await
+            .unwrap();
+        }
+
+        let (_, body) = list_users(&app, &format!("search={}", &username[..13])).await;
+        assert_eq!(body["total"], json!(1));
+        let user = &body["users"][0];
+        assert_eq!(user["username"], json!(username));
+        assert_eq!(user["task_count"], json!(2));
+        assert!(user.get("password").is_none());
+
+        // Wildcards are matched literally
+        let (_, body) = list_users(&app, "search=%25").await;
+        assert_eq!(body["total"], json!(0));
+
+        let (_, body) = list_users(
+            &app,
+            &format!("search={}&verified=false", &unverified[..13]),
+        )
+        .await;
+        assert_eq!(body["total"], json!(1));
+        let (_, body) =
+            list_users(&app, &format!("search={}&verified=true", &unverified[..13])).await;
+        assert_eq!(body["total"], json!(0));
+        let (_, body) = list_users(&app, &format!("search={}&role=admin", &username[..13])).await;
+        assert_eq!(body["total"], json!(0));
+
+        let (_, body) = list_users(&app, "page=2&per_page=1").await;
+        assert_eq!(body["users"].as_array().unwrap().len(), 1);
+        assert!(body["total"].as_u64().unwrap() >= 2);
+
+        let (status, _) = list_users(&app, "per_page=1000").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = list_users(&app, &format!("page={}", u64::MAX)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn admin_manage_user_test() {
+        let app_state = app_state_test().await;
+        let database_conn = app_state.database_conn.clone();
+        let app = create_routes(app_state).await;
+        let admin = bearer_test(1, Role::Admin).await;
+        let admin = admin.trim_start_matches("Bearer ");
+        let send = |method: http::Method, uri: String, token: &str, body| {
+            app.clone()
+                .oneshot(bearer_request(method, &uri, token, body))
+        };
+
+        // Created accounts go through the checks of a registration
+        let username = format!("{}@test.com", Uuid::new_v4());
+        for (user_request, status) in [
+            (
+                json!({ "username": "not an email", "password": "password1234", "role": "admin" }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "username": username, "password": "short", "role": "admin" }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "username": username, "password": "password1234", "role": "admin" }),
+                StatusCode::OK,
+            ),
+            (
+                json!({ "username": username, "password": "password1234", "role": "user" }),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = send(http::Method::POST, "/user".to_string(), admin, user_request)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        let response = send(
+            http::Method::GET,
+            format!("/user/{}", username),
+            admin,
+            json!({}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let details = response_json(response).await;
+        assert_eq!(details["role"], json!("admin"));
+        assert_eq!(details["verified_at"], json!(null));
+        assert!(details.get("password").is_none());
+
+        let (username, password) = register_test(&app).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let user_uri = format!("/user/{}", username);
+
+        // Disabled users are logged out and can't log back in
+        let response = send(
+            http::Method::POST,
+            format!("{}/disable", user_uri),
+            admin,
+            json!({}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(http::Method::GET, "/me".to_string(), &token, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Nor can an admin act as them
+        let response = send(
+            http::Method::POST,
+            format!("{}/impersonate", user_uri),
+            admin,
+            json!({}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send(
+            http::Method::POST,
+            format!("{}/enable", user_uri),
+            admin,
+            json!({}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // Tokens issued in the second of the revoke-all are revoked too
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // A new role applies right away, tokens with the old one are revoked
+        let response = send(
+            http::Method::PUT,
+            format!("{}/role", user_uri),
+            admin,
+            json!({ "role": "admin" }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(http::Method::GET, "/me".to_string(), &token, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let response = send(http::Method::GET, "/user".to_string(), &token, json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Forcing a reset leaves only the mailed link to get back in
+        let response = send(
+            http::Method::POST,
+            format!("{}/password_reset", user_uri),
+            admin,
+            json!({}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                json!({"username": username, "password": password, "pow": pow_test(&app).await}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/password_reset/confirm",
+                json!({ "token": mailed_token_test(&username).await, "password": "new password 1" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        login_test(&app, &username, "new password 1").await;
+
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username))
+            .one(&database_conn)
+            .await
+            .unwrap()
+            .unwrap();
+        let events = audit_events::Entity::find()
+            .filter(audit_events::Column::UserId.eq(user.id))
+            .all(&database_conn)
+            .await
+            .unwrap();
+        assert!(events.iter().all(|event| event.actor_id == Some(1)));
+        let actions: Vec<&str> = events.iter().map(|event| event.action.as_str()).collect();
+        assert_eq!(
+            actions,
+            [
+                USER_DISABLED,
+                USER_ENABLED,
+                ROLE_CHANGED,
+                PASSWORD_RESET_FORCED
+            ]
+        );
+        assert_eq!(events[2].details.as_deref(), Some("user -> admin"));
+    }
+}
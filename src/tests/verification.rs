@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use crate::tests::app::{
+        app_test, json_request, mailed_token_test, pow_test, register_unverified_test,
+    };
+    use axum::http;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    #[tokio::test]
+    async fn login_requires_verified_email_test() {
+        let app = app_test().await;
+        let (username, password) = register_unverified_test(&app).await;
+        let credentials = |pow| json!({"username": username, "password": password, "pow": pow});
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                credentials(pow_test(&app).await),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The registration mail was just sent
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/verify_email/resend",
+                json!({ "username": username }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/verify_email",
+                json!({ "token": mailed_token_test(&username).await }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/login",
+                credentials(pow_test(&app).await),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,204 @@
+#[cfg(test)]
+mod tests {
+    use crate::router::create_routes;
+    use crate::tests::app::{
+        app_state_test, bearer_request, json_request, login_test, register_test, response_json,
+    };
+    use axum::http;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use ciborium::value::Value;
+    use reqwest::Url;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use tower::ServiceExt; // for `oneshot` and `ready`
+
+    const FLAGS_REGISTRATION: u8 = 0x45; // user present and verified, attested credential
+    const FLAGS_ASSERTION: u8 = 0x05; // user present and verified
+
+    /// Authenticator holding a single P-256 credential.
+    struct SoftwareAuthenticator {
+        key_pair: EcdsaKeyPair,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+        origin: String,
+    }
+
+    impl SoftwareAuthenticator {
+        fn new(app_url: &str) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+
+            SoftwareAuthenticator {
+                key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+                    .unwrap(),
+                credential_id: Sha256::digest(pkcs8.as_ref()).to_vec(),
+                sign_count: 0,
+                origin: Url::parse(app_url).unwrap().origin().ascii_serialization(),
+            }
+        }
+
+        fn client_data(&self, ceremony: &str, options: &serde_json::Value) -> Vec<u8> {
+            serde_json::to_vec(&json!({
+                "type": ceremony,
+                "challenge": options["challenge"],
+                "origin": self.origin,
+            }))
+            .unwrap()
+        }
+
+        fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        /// Answers `navigator.credentials.create()`.
+        fn create(&self, options: &serde_json::Value) -> serde_json::Value {
+            let public_key = self.key_pair.public_key().as_ref();
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(-7)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(public_key[1..33].to_vec())),
+                (Value::from(-3), Value::Bytes(public_key[33..].to_vec())),
+            ]);
+
+            let mut authenticator_data =
+                self.authenticator_data(options["rp"]["id"].as_str().unwrap(), FLAGS_REGISTRATION);
+            authenticator_data.extend_from_slice(&[0; 16]);
+            authenticator_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            authenticator_data.extend_from_slice(&self.credential_id);
+            ciborium::ser::into_writer(&cose_key, &mut authenticator_data).unwrap();
+
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(
+                &Value::Map(vec![
+                    (Value::from("fmt"), Value::from("none")),
+                    (Value::from("attStmt"), Value::Map(Vec::new())),
+                    (Value::from("authData"), Value::Bytes(authenticator_data)),
+                ]),
+                &mut attestation_object,
+            )
+            .unwrap();
+
+            json!({
+                "id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+                "response": {
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(self.client_data("webauthn.create", options)),
+                    "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+                },
+            })
+        }
+
+        /// Answers `navigator.credentials.get()`.
+        fn get(&mut self, options: &serde_json::Value) -> serde_json::Value {
+            self.sign_count += 1;
+
+            let client_data = self.client_data("webauthn.get", options);
+            let authenticator_data =
+                self.authenticator_data(options["rpId"].as_str().unwrap(), FLAGS_ASSERTION);
+
+            let mut signed = authenticator_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature = self.key_pair.sign(&SystemRandom::new(), &signed).unwrap();
+
+            json!({
+                "id": URL_SAFE_NO_PAD.encode(&self.credential_id),
+                "response": {
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                    "authenticatorData": URL_SAFE_NO_PAD.encode(authenticator_data),
+                    "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                },
+            })
+        }
+    }
+
+    async fn post(
+        app: &Router,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(json_request(http::Method::POST, uri, body))
+            .await
+            .unwrap();
+        let status = response.status();
+
+        if status == StatusCode::OK {
+            (status, response_json(response).await)
+        } else {
+            (status, serde_json::Value::Null)
+        }
+    }
+
+    #[tokio::test]
+    async fn passkey_login_test() {
+        let app_state = app_state_test().await;
+        let mut authenticator = SoftwareAuthenticator::new(&app_state.config.app_url);
+        let app = create_routes(app_state).await;
+
+        let (username, password) = register_test(&app).await;
+        let token = login_test(&app, &username, &password).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/webauthn/register/start",
+                &token,
+                json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let options = response_json(response).await;
+
+        let mut credential = authenticator.create(&options);
+        credential["name"] = json!("Laptop");
+        let response = app
+            .clone()
+            .oneshot(bearer_request(
+                http::Method::POST,
+                "/webauthn/register/finish",
+                &token,
+                credential,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (status, options) = post(
+            &app,
+            "/webauthn/login/start",
+            json!({ "username": username }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(options["allowCredentials"].as_array().unwrap().len(), 1);
+
+        let assertion = authenticator.get(&options);
+        let (status, body) = post(&app, "/webauthn/login/finish", assertion.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["token"].is_string());
+
+        // A challenge can only be answered once
+        let (status, _) = post(&app, "/webauthn/login/finish", assertion).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // A clone of the authenticator doesn't know the counter went up
+        let (_, options) = post(&app, "/webauthn/login/start", json!({})).await;
+        authenticator.sign_count -= 1;
+        let (status, _) = post(&app, "/webauthn/login/finish", authenticator.get(&options)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,65 @@
+use chrono::Utc;
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter,
+};
+
+use crate::{
+    database::{api_keys, users},
+    utils::{
+        opaque_token::{generate_token, hash_token},
+        roles::Permission,
+    },
+};
+
+/// Tells API keys apart from access tokens in the `Authorization` header.
+pub const API_KEY_PREFIX: &str = "pat_";
+
+pub fn generate_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, generate_token())
+}
+
+pub fn format_scopes(scopes: &[Permission]) -> String {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn parse_scopes(scopes: &str) -> Result<Vec<Permission>, String> {
+    scopes.split_whitespace().map(str::parse).collect()
+}
+
+/// Finds the unexpired key and its owner, and records that it was used.
+pub async fn find_api_key(
+    database_conn: &DatabaseConnection,
+    key: &str,
+) -> Result<Option<(api_keys::Model, users::Model)>, String> {
+    let found = api_keys::Entity::find()
+        .filter(api_keys::Column::KeyHash.eq(hash_token(key)))
+        .filter(
+            Condition::any()
+                .add(api_keys::Column::ExpiresAt.is_null())
+                .add(api_keys::Column::ExpiresAt.gt(Utc::now())),
+        )
+        .find_also_related(users::Entity)
+        .filter(users::Column::DeletedAt.is_null())
+        .filter(users::Column::DisabledAt.is_null())
+        .one(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    let (api_key, user) = match found {
+        Some((api_key, Some(user))) => (api_key, user),
+        _ => return Ok(None),
+    };
+
+    api_keys::Entity::update_many()
+        .col_expr(api_keys::Column::LastUsedAt, Expr::value(Utc::now()))
+        .filter(api_keys::Column::Id.eq(api_key.id))
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(Some((api_key, user)))
+}
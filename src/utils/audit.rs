@@ -0,0 +1,39 @@
+use sea_orm::{ActiveModelTrait, ConnectionTrait, Set};
+
+use crate::database::audit_events;
+
+pub const IMPERSONATION_STARTED: &str = "impersonation.started";
+pub const IMPERSONATED_REQUEST: &str = "impersonation.request";
+pub const USER_CREATED: &str = "user.created";
+pub const USER_DELETED: &str = "user.deleted";
+pub const USER_RESTORED: &str = "user.restored";
+pub const USER_PURGED: &str = "user.purged";
+pub const USER_DISABLED: &str = "user.disabled";
+pub const USER_ENABLED: &str = "user.enabled";
+pub const ROLE_CHANGED: &str = "user.role_changed";
+pub const PASSWORD_RESET_FORCED: &str = "user.password_reset_forced";
+pub const SESSIONS_REVOKED: &str = "user.sessions_revoked";
+pub const DATA_EXPORTED: &str = "user.data_exported";
+
+/// Records an action on the account of `user_id`. `actor_id` is whoever
+/// actually performed it when it isn't the user, like an impersonating admin.
+pub async fn record_audit_event<C: ConnectionTrait>(
+    database_conn: &C,
+    user_id: Option<i32>,
+    actor_id: Option<i32>,
+    action: &str,
+    details: Option<String>,
+) -> Result<(), String> {
+    audit_events::ActiveModel {
+        user_id: Set(user_id),
+        actor_id: Set(actor_id),
+        action: Set(action.to_string()),
+        details: Set(details),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::sync::{Arc, OnceLock};
+
+use async_trait::async_trait;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+
+use crate::{
+    database::users,
+    utils::password_hash::{verify_password, PasswordHashing},
+};
+
+/// Checks the username and password of a login.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// The user the credentials belong to, `None` when they are wrong.
+    async fn authenticate(
+        &self,
+        database_conn: &DatabaseConnection,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<users::Model>, String>;
+}
+
+/// Hash verified against when the user doesn't exist, computed once.
+fn dummy_hash(password_hashing: &PasswordHashing) -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| password_hashing.hash("dummy password").unwrap())
+}
+
+/// Passwords hashed in the `users` table.
+pub struct LocalAuthenticator {
+    password_hashing: PasswordHashing,
+}
+
+impl LocalAuthenticator {
+    pub fn new(password_hashing: PasswordHashing) -> Self {
+        LocalAuthenticator { password_hashing }
+    }
+}
+
+#[async_trait]
+impl Authenticator for LocalAuthenticator {
+    async fn authenticate(
+        &self,
+        database_conn: &DatabaseConnection,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<users::Model>, String> {
+        let user = users::Entity::find()
+            .filter(users::Column::Username.eq(username))
+            .filter(users::Column::DeletedAt.is_null())
+            .one(database_conn)
+            .await
+            .map_err(|errors| errors.to_string())?;
+
+        // Unknown users get a verification too, so that the response time
+        // doesn't tell which accounts exist
+        let password_hash = match &user {
+            Some(user) => user.password.as_str(),
+            None => dummy_hash(&self.password_hashing),
+        };
+        let is_valid = verify_password(password, password_hash)?;
+
+        let user = match user {
+            Some(user) if is_valid => user,
+            _ => return Ok(None),
+        };
+
+        // The password is only known now, to upgrade a hash of older settings
+        if !self.password_hashing.needs_rehash(&user.password) {
+            return Ok(Some(user));
+        }
+
+        let password = self.password_hashing.hash(password)?;
+
+        let mut user: users::ActiveModel = user.into();
+        user.password = Set(password);
+        user.update(database_conn)
+            .await
+            .map(Some)
+            .map_err(|errors| errors.to_string())
+    }
+}
+
+/// Tries each authenticator in turn, the first accepting the credentials wins.
+/// A failing one, like an unreachable directory, doesn't stop the others: its
+/// error is only returned when none of them accepted the credentials.
+pub struct ChainedAuthenticator(pub Vec<Arc<dyn Authenticator>>);
+
+#[async_trait]
+impl Authenticator for ChainedAuthenticator {
+    async fn authenticate(
+        &self,
+        database_conn: &DatabaseConnection,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<users::Model>, String> {
+        let mut error = None;
+
+        for authenticator in &self.0 {
+            match authenticator
+                .authenticate(database_conn, username, password)
+                .await
+            {
+                Ok(Some(user)) => return Ok(Some(user)),
+                Ok(None) => {}
+                Err(errors) => {
+                    eprintln!("Authenticator failed, trying the next one: {}", errors);
+                    error = Some(errors);
+                }
+            }
+        }
+
+        match error {
+            Some(errors) => Err(errors),
+            None => Ok(None),
+        }
+    }
+}
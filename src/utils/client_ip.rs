@@ -0,0 +1,46 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::config::Config;
+
+/// Address of the client. Behind a reverse proxy (`TRUST_PROXY=true`) it is
+/// the last entry of `X-Forwarded-For`, the one added by the proxy itself.
+#[derive(Clone, Debug)]
+pub struct ClientIp(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    Config: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if Config::from_ref(state).trust_proxy {
+            let forwarded = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|header| header.to_str().ok())
+                .and_then(|header| header.rsplit(',').next())
+                .map(|ip| ip.trim().to_string());
+
+            if let Some(ip) = forwarded {
+                return Ok(ClientIp(ip));
+            }
+        }
+
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        Ok(ClientIp(ip))
+    }
+}
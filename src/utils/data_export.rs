@@ -0,0 +1,486 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use async_zip::{base::write::ZipFileWriter, Compression, ZipDateTime, ZipEntryBuilder};
+use chrono::Utc;
+use futures_lite::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use hyper::body::{Bytes, Sender};
+use sea_orm::{
+    prelude::DateTimeWithTimeZone, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, Select,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{
+    api_keys, audit_events, recovery_codes, sessions, tasks, user_identities, users,
+    webauthn_credentials,
+};
+
+/// Rows read and sent at once.
+const PAGE_SIZE: u64 = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One JSON object with a field per section.
+    #[default]
+    Json,
+    /// One JSON file per section.
+    Zip,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Zip => "application/zip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Zip => "zip",
+        }
+    }
+}
+
+/// The `users` row, without the password hash nor the other secrets.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedUser {
+    pub id: i32,
+    pub username: String,
+    pub role: String,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub verified_at: Option<DateTimeWithTimeZone>,
+    pub totp_enabled_at: Option<DateTimeWithTimeZone>,
+    pub disabled_at: Option<DateTimeWithTimeZone>,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+}
+
+impl From<users::Model> for ExportedUser {
+    fn from(user: users::Model) -> Self {
+        ExportedUser {
+            id: user.id,
+            username: user.username,
+            role: user.role,
+            display_name: user.display_name,
+            timezone: user.timezone,
+            locale: user.locale,
+            verified_at: user.verified_at,
+            totp_enabled_at: user.totp_enabled_at,
+            disabled_at: user.disabled_at,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedTask {
+    pub id: i32,
+    pub priority: Option<String>,
+    pub title: String,
+    pub description: Option<String>,
+    pub completed_at: Option<DateTimeWithTimeZone>,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    pub is_default: Option<bool>,
+}
+
+impl From<tasks::Model> for ExportedTask {
+    fn from(task: tasks::Model) -> Self {
+        ExportedTask {
+            id: task.id,
+            priority: task.priority,
+            title: task.title,
+            description: task.description,
+            completed_at: task.completed_at,
+            deleted_at: task.deleted_at,
+            is_default: task.is_default,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedSession {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: String,
+    pub created_at: DateTimeWithTimeZone,
+    pub last_seen_at: DateTimeWithTimeZone,
+    pub revoked_at: Option<DateTimeWithTimeZone>,
+}
+
+impl From<sessions::Model> for ExportedSession {
+    fn from(session: sessions::Model) -> Self {
+        ExportedSession {
+            id: session.id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            revoked_at: session.revoked_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedAuditEvent {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub details: Option<String>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+impl From<audit_events::Model> for ExportedAuditEvent {
+    fn from(event: audit_events::Model) -> Self {
+        ExportedAuditEvent {
+            id: event.id,
+            actor_id: event.actor_id,
+            action: event.action,
+            details: event.details,
+            created_at: event.created_at,
+        }
+    }
+}
+
+/// The key itself is only known by its hash, which stays out of the export.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTimeWithTimeZone>,
+    pub last_used_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+impl From<api_keys::Model> for ExportedApiKey {
+    fn from(api_key: api_keys::Model) -> Self {
+        ExportedApiKey {
+            id: api_key.id,
+            name: api_key.name,
+            scopes: api_key
+                .scopes
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            expires_at: api_key.expires_at,
+            last_used_at: api_key.last_used_at,
+            created_at: api_key.created_at,
+        }
+    }
+}
+
+/// Without the credential id and public key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedWebauthnCredential {
+    pub id: i32,
+    pub name: String,
+    pub last_used_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+impl From<webauthn_credentials::Model> for ExportedWebauthnCredential {
+    fn from(credential: webauthn_credentials::Model) -> Self {
+        ExportedWebauthnCredential {
+            id: credential.id,
+            name: credential.name,
+            last_used_at: credential.last_used_at,
+            created_at: credential.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedIdentity {
+    pub id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+impl From<user_identities::Model> for ExportedIdentity {
+    fn from(identity: user_identities::Model) -> Self {
+        ExportedIdentity {
+            id: identity.id,
+            issuer: identity.issuer,
+            subject: identity.subject,
+            created_at: identity.created_at,
+        }
+    }
+}
+
+/// Whether each MFA recovery code was used, not the code.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedRecoveryCode {
+    pub id: i32,
+    pub used_at: Option<DateTimeWithTimeZone>,
+}
+
+impl From<recovery_codes::Model> for ExportedRecoveryCode {
+    fn from(code: recovery_codes::Model) -> Self {
+        ExportedRecoveryCode {
+            id: code.id,
+            used_at: code.used_at,
+        }
+    }
+}
+
+/// The response body, as a writer for the zip archive.
+struct BodyWriter(Sender);
+
+impl AsyncWrite for BodyWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.0.poll_ready(cx))
+            .map_err(|errors| io::Error::new(io::ErrorKind::BrokenPipe, errors))?;
+        self.0
+            .try_send_data(Bytes::copy_from_slice(buf))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A part of the export, a JSON field or a zip entry.
+#[derive(Debug, Clone, Copy)]
+enum Section {
+    User,
+    Tasks,
+    Sessions,
+    ApiKeys,
+    WebauthnCredentials,
+    Identities,
+    RecoveryCodes,
+    AuditEvents,
+}
+
+impl Section {
+    const ALL: [Section; 8] = [
+        Section::User,
+        Section::Tasks,
+        Section::Sessions,
+        Section::ApiKeys,
+        Section::WebauthnCredentials,
+        Section::Identities,
+        Section::RecoveryCodes,
+        Section::AuditEvents,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Section::User => "user",
+            Section::Tasks => "tasks",
+            Section::Sessions => "sessions",
+            Section::ApiKeys => "api_keys",
+            Section::WebauthnCredentials => "webauthn_credentials",
+            Section::Identities => "identities",
+            Section::RecoveryCodes => "recovery_codes",
+            Section::AuditEvents => "audit_events",
+        }
+    }
+}
+
+/// Every row of the query as a JSON array, read a page at a time.
+async fn write_rows<W, E, T>(
+    out: &mut W,
+    database_conn: &DatabaseConnection,
+    query: Select<E>,
+) -> Result<(), String>
+where
+    W: AsyncWrite + Unpin,
+    E: EntityTrait,
+    E::Model: Sync,
+    T: Serialize + From<E::Model>,
+{
+    let mut pages = query.paginate(database_conn, PAGE_SIZE);
+    let mut separator = b'[';
+    while let Some(rows) = pages
+        .fetch_and_next()
+        .await
+        .map_err(|errors| errors.to_string())?
+    {
+        let mut page = Vec::new();
+        for row in rows {
+            page.push(separator);
+            separator = b',';
+            serde_json::to_writer(&mut page, &T::from(row)).map_err(|errors| errors.to_string())?;
+        }
+        out.write_all(&page)
+            .await
+            .map_err(|errors| errors.to_string())?;
+    }
+
+    // The opening bracket is still due when there were no rows
+    let end: &[u8] = if separator == b'[' { b"[]" } else { b"]" };
+    out.write_all(end)
+        .await
+        .map_err(|errors| errors.to_string())
+}
+
+async fn write_section<W: AsyncWrite + Unpin>(
+    out: &mut W,
+    database_conn: &DatabaseConnection,
+    user: &users::Model,
+    section: Section,
+) -> Result<(), String> {
+    match section {
+        Section::User => {
+            let user = serde_json::to_vec(&ExportedUser::from(user.clone()))
+                .map_err(|errors| errors.to_string())?;
+            out.write_all(&user)
+                .await
+                .map_err(|errors| errors.to_string())
+        }
+        Section::Tasks => {
+            write_rows::<_, _, ExportedTask>(
+                out,
+                database_conn,
+                tasks::Entity::find()
+                    .filter(tasks::Column::UserId.eq(user.id))
+                    .order_by_asc(tasks::Column::Id),
+            )
+            .await
+        }
+        Section::Sessions => {
+            write_rows::<_, _, ExportedSession>(
+                out,
+                database_conn,
+                sessions::Entity::find()
+                    .filter(sessions::Column::UserId.eq(user.id))
+                    .order_by_asc(sessions::Column::Id),
+            )
+            .await
+        }
+        Section::ApiKeys => {
+            write_rows::<_, _, ExportedApiKey>(
+                out,
+                database_conn,
+                api_keys::Entity::find()
+                    .filter(api_keys::Column::UserId.eq(user.id))
+                    .order_by_asc(api_keys::Column::Id),
+            )
+            .await
+        }
+        Section::WebauthnCredentials => {
+            write_rows::<_, _, ExportedWebauthnCredential>(
+                out,
+                database_conn,
+                webauthn_credentials::Entity::find()
+                    .filter(webauthn_credentials::Column::UserId.eq(user.id))
+                    .order_by_asc(webauthn_credentials::Column::Id),
+            )
+            .await
+        }
+        Section::Identities => {
+            write_rows::<_, _, ExportedIdentity>(
+                out,
+                database_conn,
+                user_identities::Entity::find()
+                    .filter(user_identities::Column::UserId.eq(user.id))
+                    .order_by_asc(user_identities::Column::Id),
+            )
+            .await
+        }
+        Section::RecoveryCodes => {
+            write_rows::<_, _, ExportedRecoveryCode>(
+                out,
+                database_conn,
+                recovery_codes::Entity::find()
+                    .filter(recovery_codes::Column::UserId.eq(user.id))
+                    .order_by_asc(recovery_codes::Column::Id),
+            )
+            .await
+        }
+        Section::AuditEvents => {
+            write_rows::<_, _, ExportedAuditEvent>(
+                out,
+                database_conn,
+                audit_events::Entity::find()
+                    .filter(audit_events::Column::UserId.eq(user.id))
+                    .order_by_asc(audit_events::Column::Id),
+            )
+            .await
+        }
+    }
+}
+
+/// Writes the export as it is read. Zip entries are stored uncompressed with
+/// their CRC and size after the content, so nothing has to be held back.
+async fn write_export<W: AsyncWrite + Unpin>(
+    out: &mut W,
+    database_conn: &DatabaseConnection,
+    user: &users::Model,
+    format: ExportFormat,
+) -> Result<(), String> {
+    match format {
+        ExportFormat::Json => {
+            for (index, section) in Section::ALL.into_iter().enumerate() {
+                let separator = if index == 0 { "{" } else { "," };
+                out.write_all(format!("{}\"{}\":", separator, section.name()).as_bytes())
+                    .await
+                    .map_err(|errors| errors.to_string())?;
+                write_section(out, database_conn, user, section).await?;
+            }
+            out.write_all(b"}")
+                .await
+                .map_err(|errors| errors.to_string())?;
+        }
+        ExportFormat::Zip => {
+            let modified = ZipDateTime::from_chrono(&Utc::now());
+            let mut archive = ZipFileWriter::new(&mut *out);
+            for section in Section::ALL {
+                let entry = ZipEntryBuilder::new(
+                    format!("{}.json", section.name()).into(),
+                    Compression::Stored,
+                )
+                .last_modification_date(modified);
+                let mut entry = archive
+                    .write_entry_stream(entry)
+                    .await
+                    .map_err(|errors| errors.to_string())?;
+                write_section(&mut entry, database_conn, user, section).await?;
+                entry.close().await.map_err(|errors| errors.to_string())?;
+            }
+            archive.close().await.map_err(|errors| errors.to_string())?;
+        }
+    }
+
+    out.flush().await.map_err(|errors| errors.to_string())
+}
+
+/// Streams everything tied to the user into `sender` from a background task,
+/// so large accounts are never held in memory. A failure midway aborts the
+/// body, the client then sees a truncated download rather than a valid one.
+pub fn spawn_data_export(
+    database_conn: DatabaseConnection,
+    user: users::Model,
+    format: ExportFormat,
+    sender: Sender,
+) {
+    tokio::spawn(async move {
+        let mut out = BufWriter::new(BodyWriter(sender));
+
+        if let Err(errors) = write_export(&mut out, &database_conn, &user, format).await {
+            eprintln!("Failed to export data of user {}: {}", user.id, errors);
+            out.into_inner().0.abort();
+        }
+    });
+}
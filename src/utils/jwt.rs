@@ -1,65 +1,197 @@
-use axum::{
-    Json,
-};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use serde::{Deserialize, Serialize};
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
+use sea_orm::prelude::Uuid;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::utils::{jwt_keys::JwtKeys, opaque_token::hash_token, roles::Role};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-    id: i32,
-    is_admin: bool,
-    exp: usize,
-    iat: usize,
+    pub id: i32,
+    pub is_admin: bool,
+    pub role: Role,
+    pub jti: Uuid,
+    pub exp: usize,
+    pub iat: usize,
+    /// Session the token was issued for, see `utils::session`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i32>,
+    /// Hash of the CSRF token of a cookie session, see `create_cookie_token`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csrf: Option<String>,
+    /// The admin acting as the user, see `create_impersonation_token`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// Actor claim of RFC 8693, the party really behind a delegated token.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: i32,
+}
+
+/// Short-lived token proving that a first login step succeeded, it can only
+/// be exchanged at the endpoint expecting its audience.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub id: i32,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+}
+
+/// Proof-of-work puzzle handed out before registering or logging in, see
+/// `utils::proof_of_work`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PowClaims {
+    pub jti: Uuid,
+    pub aud: String,
+    /// Leading zero bits required in the hash of the solution.
+    pub difficulty: u32,
+    pub exp: usize,
+    pub iat: usize,
 }
 
-pub async fn create_token(jwt_secret: String, id: i32) -> Result<String, String> {
+/// Lifetime of an access token, also how long a revoked one must be remembered.
+pub const TOKEN_HOURS: i64 = 24;
+
+/// Lifetime of an impersonation token, there is no way to renew it.
+pub const IMPERSONATION_MINUTES: i64 = 15;
+
+const POW_AUDIENCE: &str = "pow";
+
+fn access_claims(id: i32, role: Role, sid: Option<i32>, csrf: Option<String>) -> Claims {
     let created_at = Utc::now();
-    let expires_at = created_at + Duration::hours(24);
+    let expires_at = created_at + Duration::hours(TOKEN_HOURS);
 
-    let claims = Claims {
+    Claims {
         id,
-        is_admin: false,
+        is_admin: role == Role::Admin,
+        role,
+        jti: Uuid::new_v4(),
         exp: expires_at.timestamp() as usize,
         iat: created_at.timestamp() as usize,
-    };
+        sid,
+        csrf,
+        act: None,
+    }
+}
+
+pub async fn create_token(
+    jwt_keys: &JwtKeys,
+    id: i32,
+    role: Role,
+    sid: Option<i32>,
+) -> Result<String, String> {
+    sign(jwt_keys, &access_claims(id, role, sid, None))
+}
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(jwt_secret.as_bytes()),
+/// Token for the session cookie, bound to the CSRF token the client must send
+/// back with state-changing requests.
+pub async fn create_cookie_token(
+    jwt_keys: &JwtKeys,
+    id: i32,
+    role: Role,
+    sid: Option<i32>,
+    csrf_token: &str,
+) -> Result<String, String> {
+    sign(
+        jwt_keys,
+        &access_claims(id, role, sid, Some(hash_token(csrf_token))),
     )
-    .map_err(|errors| errors.to_string())?;
+}
 
-    Ok(token)
+/// Token letting the admin `actor_id` act as the user, without a session.
+pub async fn create_impersonation_token(
+    jwt_keys: &JwtKeys,
+    id: i32,
+    role: Role,
+    actor_id: i32,
+) -> Result<String, String> {
+    let mut claims = access_claims(id, role, None, None);
+    claims.exp = (Utc::now() + Duration::minutes(IMPERSONATION_MINUTES)).timestamp() as usize;
+    claims.act = Some(Actor { sub: actor_id });
+
+    sign(jwt_keys, &claims)
 }
 
-async fn decode_token(jwt_secret: String, token: String) -> Result<Json<Claims>, String> {
-    let token_data = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(jwt_secret.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    )
-    .map_err(|errors| errors.to_string())?;
+pub async fn decode_token(jwt_keys: &JwtKeys, token: String) -> Result<Claims, String> {
+    verify(jwt_keys, &token, None)
+}
+
+pub async fn create_challenge_token(
+    jwt_keys: &JwtKeys,
+    id: i32,
+    audience: &str,
+    lifetime: Duration,
+) -> Result<String, String> {
+    let created_at = Utc::now();
 
-    return Ok(Json(token_data.claims));
+    let claims = ChallengeClaims {
+        id,
+        aud: audience.to_string(),
+        exp: (created_at + lifetime).timestamp() as usize,
+        iat: created_at.timestamp() as usize,
+    };
+
+    sign(jwt_keys, &claims)
 }
 
-pub async fn refresh_token(jwt_secret: String, token: String) -> Result<String, String> {
-    let token = decode_token(jwt_secret.clone(), token)
-        .await
-        .map_err(|errors| errors.to_string())?;
+pub async fn decode_challenge_token(
+    jwt_keys: &JwtKeys,
+    token: String,
+    audience: &str,
+) -> Result<ChallengeClaims, String> {
+    verify(jwt_keys, &token, Some(audience))
+}
+
+pub async fn create_pow_token(
+    jwt_keys: &JwtKeys,
+    difficulty: u32,
+    lifetime: Duration,
+) -> Result<String, String> {
+    let created_at = Utc::now();
 
-    let claims = Claims {
-        id: token.id,
-        is_admin: token.is_admin,
-        exp: token.exp,
-        iat: token.iat,
+    let claims = PowClaims {
+        jti: Uuid::new_v4(),
+        aud: POW_AUDIENCE.to_string(),
+        difficulty,
+        exp: (created_at + lifetime).timestamp() as usize,
+        iat: created_at.timestamp() as usize,
     };
 
-    let token = create_token(jwt_secret, claims.id)
-        .await
-        .map_err(|errors| errors.to_string())?;
+    sign(jwt_keys, &claims)
+}
+
+pub async fn decode_pow_token(jwt_keys: &JwtKeys, token: &str) -> Result<PowClaims, String> {
+    verify(jwt_keys, token, Some(POW_AUDIENCE))
+}
+
+fn sign<T: Serialize>(jwt_keys: &JwtKeys, claims: &T) -> Result<String, String> {
+    let mut header = Header::new(jwt_keys.algorithm());
+    header.kid = jwt_keys.active_kid();
 
-    Ok(token)
-}
\ No newline at end of file
+    encode(&header, claims, jwt_keys.encoding_key()).map_err(|errors| errors.to_string())
+}
+
+fn verify<T: DeserializeOwned>(
+    jwt_keys: &JwtKeys,
+    token: &str,
+    audience: Option<&str>,
+) -> Result<T, String> {
+    let header = decode_header(token).map_err(|errors| errors.to_string())?;
+    let (algorithm, decoding_key) = jwt_keys
+        .decoding_key(header.kid.as_deref())
+        .ok_or("Unknown signing key")?;
+
+    let mut validation = Validation::new(algorithm);
+    if let Some(audience) = audience {
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "aud"]);
+    }
+
+    let token_data =
+        decode::<T>(token, decoding_key, &validation).map_err(|errors| errors.to_string())?;
+
+    Ok(token_data.claims)
+}
@@ -0,0 +1,177 @@
+use std::{fs, path::Path, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+
+struct VerifyingKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    decoding_key: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+struct Keys {
+    active_kid: Option<String>,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    verifying_keys: Vec<VerifyingKey>,
+}
+
+/// Keys used to sign and verify the tokens of `utils::jwt`.
+///
+/// Asymmetric keys are identified by a `kid`: tokens are signed with the
+/// active one, and every loaded key is still accepted for verification so a
+/// new key can be rolled out before the old one is removed. Without keys the
+/// app falls back to HS256 with `JWT_SECRET`, which publishes no JWKS.
+#[derive(Clone)]
+pub struct JwtKeys(Arc<Keys>);
+
+impl JwtKeys {
+    pub fn from_secret(jwt_secret: &str) -> Self {
+        JwtKeys(Arc::new(Keys {
+            active_kid: None,
+            algorithm: Algorithm::HS256,
+            encoding_key: EncodingKey::from_secret(jwt_secret.as_bytes()),
+            verifying_keys: vec![VerifyingKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                decoding_key: DecodingKey::from_secret(jwt_secret.as_bytes()),
+                jwk: None,
+            }],
+        }))
+    }
+
+    /// Loads every `<kid>.pem` PKCS#8 private key (Ed25519 or RSA) of `dir`.
+    pub fn from_dir(dir: impl AsRef<Path>, active_kid: &str) -> Result<Self, String> {
+        let mut pems = Vec::new();
+
+        for entry in fs::read_dir(dir).map_err(|errors| errors.to_string())? {
+            let path = entry.map_err(|errors| errors.to_string())?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("pem") {
+                continue;
+            }
+
+            let kid = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or(format!("Invalid key file name: {}", path.display()))?
+                .to_string();
+            let pem = fs::read_to_string(&path).map_err(|errors| errors.to_string())?;
+
+            pems.push((kid, pem));
+        }
+
+        Self::from_pems(pems, active_kid)
+    }
+
+    pub fn from_pems(pems: Vec<(String, String)>, active_kid: &str) -> Result<Self, String> {
+        let mut active = None;
+        let mut verifying_keys = Vec::new();
+
+        for (kid, pem) in pems {
+            let der = pem::parse(&pem)
+                .map_err(|errors| format!("Key {}: {}", kid, errors))?
+                .contents;
+
+            let (algorithm, parameters, encoding_key) =
+                if let Ok(key_pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der) {
+                    let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+                    });
+                    let encoding_key = EncodingKey::from_ed_pem(pem.as_bytes());
+
+                    (Algorithm::EdDSA, parameters, encoding_key)
+                } else if let Ok(key_pair) = RsaKeyPair::from_pkcs8(&der) {
+                    let public_key = key_pair.public_key();
+                    let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD
+                            .encode(public_key.modulus().big_endian_without_leading_zero()),
+                        e: URL_SAFE_NO_PAD
+                            .encode(public_key.exponent().big_endian_without_leading_zero()),
+                    });
+                    let encoding_key = EncodingKey::from_rsa_pem(pem.as_bytes());
+
+                    (Algorithm::RS256, parameters, encoding_key)
+                } else {
+                    return Err(format!("Key {}: not an Ed25519 or RSA PKCS#8 key", kid));
+                };
+
+            let jwk = Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    algorithm: Some(algorithm),
+                    key_id: Some(kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: parameters,
+            };
+            let decoding_key =
+                DecodingKey::from_jwk(&jwk).map_err(|errors| format!("Key {}: {}", kid, errors))?;
+
+            if kid == active_kid {
+                let encoding_key =
+                    encoding_key.map_err(|errors| format!("Key {}: {}", kid, errors))?;
+                active = Some((algorithm, encoding_key));
+            }
+
+            verifying_keys.push(VerifyingKey {
+                kid: Some(kid),
+                algorithm,
+                decoding_key,
+                jwk: Some(jwk),
+            });
+        }
+
+        let (algorithm, encoding_key) =
+            active.ok_or(format!("Active key {} not found", active_kid))?;
+
+        Ok(JwtKeys(Arc::new(Keys {
+            active_kid: Some(active_kid.to_string()),
+            algorithm,
+            encoding_key,
+            verifying_keys,
+        })))
+    }
+
+    pub fn active_kid(&self) -> Option<String> {
+        self.0.active_kid.clone()
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.0.algorithm
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.0.encoding_key
+    }
+
+    /// Finds the key a token claims to be signed with.
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<(Algorithm, &DecodingKey)> {
+        self.0
+            .verifying_keys
+            .iter()
+            .find(|key| key.kid.as_deref() == kid)
+            .map(|key| (key.algorithm, &key.decoding_key))
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .0
+                .verifying_keys
+                .iter()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use ldap3::{dn_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+    TransactionTrait,
+};
+
+use crate::{
+    config::LdapConfig,
+    database::{user_identities, users},
+    utils::{
+        authenticator::Authenticator, opaque_token::generate_token, password_hash::PasswordHashing,
+        roles::Role,
+    },
+};
+
+const LDAP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Result code of a bind with a wrong DN or password
+const INVALID_CREDENTIALS: u32 = 49;
+/// `issuer` of the `user_identities` rows linking accounts to directory
+/// entries, whose DN is the `subject`
+pub const LDAP_ISSUER: &str = "ldap";
+
+/// Binds to the directory as the user. Directory users get a `users` row
+/// linked to their entry on their first login, and the role of that row
+/// follows their groups on every login.
+pub struct LdapAuthenticator {
+    config: LdapConfig,
+    password_hashing: PasswordHashing,
+}
+
+impl LdapAuthenticator {
+    pub fn new(config: LdapConfig, password_hashing: PasswordHashing) -> Self {
+        LdapAuthenticator {
+            config,
+            password_hashing,
+        }
+    }
+
+    fn user_dn(&self, username: &str) -> String {
+        self.config
+            .dn_template
+            .replace("{username}", &dn_escape(username))
+    }
+
+    /// The highest role granted by the groups, `Role::User` by default.
+    fn role_of(&self, groups: &[String]) -> Role {
+        let granted = |role: Role| {
+            self.config.role_groups.iter().any(|(group, group_role)| {
+                *group_role == role
+                    && groups
+                        .iter()
+                        .any(|member_of| member_of.eq_ignore_ascii_case(group))
+            })
+        };
+
+        if granted(Role::Admin) {
+            Role::Admin
+        } else {
+            Role::User
+        }
+    }
+
+    /// Groups of the user, `None` when the password is wrong.
+    async fn bind(&self, dn: &str, password: &str) -> Result<Option<Vec<String>>, String> {
+        let settings = LdapConnSettings::new().set_conn_timeout(LDAP_TIMEOUT);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(|errors| errors.to_string())?;
+        ldap3::drive!(conn);
+
+        let groups = bind_and_read_groups(&mut ldap, dn, password).await;
+        let _ = ldap.unbind().await;
+
+        groups
+    }
+
+    /// The account linked to the directory entry, created on the first login.
+    /// A local account of the same name is never taken over, its owner may
+    /// not be the directory user: that login is refused.
+    async fn provision_user(
+        &self,
+        database_conn: &DatabaseConnection,
+        username: &str,
+        dn: &str,
+        role: Role,
+    ) -> Result<Option<users::Model>, String> {
+        let identity = user_identities::Entity::find()
+            .filter(user_identities::Column::Issuer.eq(LDAP_ISSUER))
+            .filter(user_identities::Column::Subject.eq(dn))
+            .find_also_related(users::Entity)
+            .one(database_conn)
+            .await
+            .map_err(|errors| errors.to_string())?;
+
+        match identity {
+            // Deleting the account locally locks the directory user out too
+            Some((_, Some(user))) if user.deleted_at.is_some() => Ok(None),
+            Some((_, Some(user))) if user.role == role.as_str() && user.verified_at.is_some() => {
+                Ok(Some(user))
+            }
+            Some((_, Some(user))) => {
+                // The directory vouches for the entry, and owns its groups
+                let verified_at = user.verified_at.unwrap_or_else(|| Utc::now().into());
+                let mut user: users::ActiveModel = user.into();
+                user.role = Set(role.as_str().to_string());
+                user.verified_at = Set(Some(verified_at));
+                user.update(database_conn)
+                    .await
+                    .map(Some)
+                    .map_err(|errors| errors.to_string())
+            }
+            _ => self.create_user(database_conn, username, dn, role).await,
+        }
+    }
+
+    async fn create_user(
+        &self,
+        database_conn: &DatabaseConnection,
+        username: &str,
+        dn: &str,
+        role: Role,
+    ) -> Result<Option<users::Model>, String> {
+        let existing = users::Entity::find()
+            .filter(users::Column::Username.eq(username))
+            .one(database_conn)
+            .await
+            .map_err(|errors| errors.to_string())?;
+        if existing.is_some() {
+            println!("Directory login refused, {} is a local account", username);
+            return Ok(None);
+        }
+
+        // The directory owns the password, nobody knows the local one
+        let password = self.password_hashing.hash(&generate_token())?;
+
+        let transaction = database_conn
+            .begin()
+            .await
+            .map_err(|errors| errors.to_string())?;
+
+        let user = users::ActiveModel {
+            username: Set(username.to_string()),
+            password: Set(password),
+            role: Set(role.as_str().to_string()),
+            verified_at: Set(Some(Utc::now().into())),
+            ..Default::default()
+        }
+        .insert(&transaction)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+        user_identities::ActiveModel {
+            user_id: Set(user.id),
+            issuer: Set(LDAP_ISSUER.to_string()),
+            subject: Set(dn.to_string()),
+            ..Default::default()
+        }
+        .insert(&transaction)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|errors| errors.to_string())?;
+
+        Ok(Some(user))
+    }
+}
+
+async fn bind_and_read_groups(
+    ldap: &mut Ldap,
+    dn: &str,
+    password: &str,
+) -> Result<Option<Vec<String>>, String> {
+    let result = ldap
+        .with_timeout(LDAP_TIMEOUT)
+        .simple_bind(dn, password)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    match result.rc {
+        0 => {}
+        INVALID_CREDENTIALS => return Ok(None),
+        _ => return Err(result.to_string()),
+    }
+
+    let (entries, _) = ldap
+        .with_timeout(LDAP_TIMEOUT)
+        .search(dn, Scope::Base, "(objectClass=*)", vec!["memberOf"])
+        .await
+        .and_then(|result| result.success())
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(Some(
+        entries
+            .into_iter()
+            .flat_map(|entry| {
+                SearchEntry::construct(entry)
+                    .attrs
+                    .remove("memberOf")
+                    .unwrap_or_default()
+            })
+            .collect(),
+    ))
+}
+
+#[async_trait]
+impl Authenticator for LdapAuthenticator {
+    async fn authenticate(
+        &self,
+        database_conn: &DatabaseConnection,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<users::Model>, String> {
+        // An empty password would be an anonymous bind, which always succeeds
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let dn = self.user_dn(username);
+        let groups = match self.bind(&dn, password).await? {
+            Some(groups) => groups,
+            None => return Ok(None),
+        };
+
+        self.provision_user(database_conn, username, &dn, self.role_of(&groups))
+            .await
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Failures allowed for an account before it gets locked
+const ACCOUNT_FREE_ATTEMPTS: u32 = 5;
+/// Failures allowed from an address, higher since users can share one
+const IP_FREE_ATTEMPTS: u32 = 20;
+const FIRST_LOCK: Duration = Duration::from_secs(30);
+const MAX_LOCK: Duration = Duration::from_secs(15 * 60);
+/// Failures are forgotten after this long without a new one
+const FAILURE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed logins per account and per client address, and locks them
+/// out for a duration doubling with each failure past the free attempts.
+///
+/// Accounts are keyed by the submitted username whether it exists or not, so
+/// a lockout doesn't reveal which accounts exist. The counts live in memory
+/// and are per instance.
+#[derive(Clone, Default)]
+pub struct LoginThrottle {
+    failures: Arc<Mutex<HashMap<String, Failures>>>,
+}
+
+impl LoginThrottle {
+    fn account_key(username: &str) -> String {
+        format!("account:{}", username.to_lowercase())
+    }
+
+    fn ip_key(ip: &str) -> String {
+        format!("ip:{}", ip)
+    }
+
+    /// Remaining lockout of the account or of the address, if any.
+    pub fn locked_for(&self, username: &str, ip: &str) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+
+        [Self::account_key(username), Self::ip_key(ip)]
+            .iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max()
+    }
+
+    pub fn record_failure(&self, username: &str, ip: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+
+        failures.retain(|_, failure| now - failure.last_failure < FAILURE_WINDOW);
+
+        for (key, free_attempts) in [
+            (Self::account_key(username), ACCOUNT_FREE_ATTEMPTS),
+            (Self::ip_key(ip), IP_FREE_ATTEMPTS),
+        ] {
+            let failure = failures.entry(key).or_insert(Failures {
+                count: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+
+            failure.count += 1;
+            failure.last_failure = now;
+
+            if failure.count >= free_attempts {
+                let exponent = (failure.count - free_attempts).min(16);
+                let lock = (FIRST_LOCK * 2u32.pow(exponent)).min(MAX_LOCK);
+                failure.locked_until = Some(now + lock);
+            }
+        }
+    }
+
+    /// Only the account is cleared, the address keeps its count so that
+    /// logging into an own account doesn't reset a credential stuffing run.
+    pub fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&Self::account_key(username));
+    }
+}
@@ -1 +1,24 @@
+pub mod api_key;
+pub mod audit;
+pub mod authenticator;
+pub mod client_ip;
+pub mod data_export;
 pub mod jwt;
+pub mod jwt_keys;
+pub mod ldap;
+pub mod login_throttle;
+pub mod mailer;
+pub mod oidc;
+pub mod opaque_token;
+pub mod password_hash;
+pub mod password_policy;
+pub mod proof_of_work;
+pub mod refresh_token;
+pub mod revocation;
+pub mod roles;
+pub mod session;
+pub mod session_cookie;
+pub mod totp;
+pub mod user_purge;
+pub mod user_token;
+pub mod webauthn;
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{config::OidcConfig, utils::opaque_token::generate_token};
+
+/// Time given to the user to log in at the provider
+const PENDING_LOGIN_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims of the ID token the app relies on.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub nonce: Option<String>,
+}
+
+struct PendingLogin {
+    code_verifier: String,
+    nonce: String,
+    started_at: Instant,
+}
+
+/// Authorization code flow with PKCE against the configured provider.
+///
+/// Logins started by `authorization_url` are kept in memory, keyed by their
+/// `state`, until the provider redirects back to the callback. Discovery and
+/// provider keys are fetched on each login so key rotations are picked up.
+#[derive(Clone)]
+pub struct OidcClient {
+    config: OidcConfig,
+    redirect_uri: String,
+    http: reqwest::Client,
+    pending_logins: Arc<Mutex<HashMap<String, PendingLogin>>>,
+}
+
+fn bad_gateway(errors: impl ToString) -> (StatusCode, String) {
+    (StatusCode::BAD_GATEWAY, errors.to_string())
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig, app_url: &str) -> Self {
+        OidcClient {
+            config,
+            redirect_uri: format!("{}/oidc/callback", app_url),
+            http: reqwest::Client::new(),
+            pending_logins: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        self.config.issuer.trim_end_matches('/')
+    }
+
+    async fn discover(&self) -> Result<Discovery, String> {
+        let discovery: Discovery = self
+            .http
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                self.issuer()
+            ))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|errors| errors.to_string())?
+            .json()
+            .await
+            .map_err(|errors| errors.to_string())?;
+
+        if discovery.issuer.trim_end_matches('/') != self.issuer() {
+            return Err(format!("Unexpected issuer: {}", discovery.issuer));
+        }
+
+        Ok(discovery)
+    }
+
+    /// Starts a login and returns the provider page to send the user to.
+    pub async fn authorization_url(&self) -> Result<String, String> {
+        let discovery = self.discover().await?;
+
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let mut url =
+            Url::parse(&discovery.authorization_endpoint).map_err(|errors| errors.to_string())?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", "openid email")
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending_logins = self.pending_logins.lock().unwrap();
+        pending_logins.retain(|_, login| login.started_at.elapsed() < PENDING_LOGIN_LIFETIME);
+        pending_logins.insert(
+            state,
+            PendingLogin {
+                code_verifier,
+                nonce,
+                started_at: Instant::now(),
+            },
+        );
+
+        Ok(url.to_string())
+    }
+
+    /// Redeems the code the provider redirected back with, and returns the
+    /// claims of the verified ID token. A `state` can only be used once.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        state: &str,
+    ) -> Result<IdTokenClaims, (StatusCode, String)> {
+        let pending_login = self
+            .pending_logins
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|login| login.started_at.elapsed() < PENDING_LOGIN_LIFETIME)
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                "Login is not valid or expired".to_string(),
+            ))?;
+
+        let discovery = self.discover().await.map_err(bad_gateway)?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &pending_login.code_verifier),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(bad_gateway)?;
+        if !response.status().is_success() {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "The identity provider refused the code".to_string(),
+            ));
+        }
+        let token_response: TokenResponse = response.json().await.map_err(bad_gateway)?;
+
+        let jwks: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(bad_gateway)?
+            .json()
+            .await
+            .map_err(bad_gateway)?;
+
+        let claims = verify_id_token(
+            &token_response.id_token,
+            &jwks,
+            &discovery.issuer,
+            &self.config.client_id,
+        )
+        .map_err(|errors| (StatusCode::UNAUTHORIZED, errors))?;
+
+        if claims.nonce.as_deref() != Some(pending_login.nonce.as_str()) {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid nonce".to_string()));
+        }
+
+        Ok(claims)
+    }
+}
+
+fn verify_id_token(
+    id_token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+) -> Result<IdTokenClaims, String> {
+    let header = decode_header(id_token).map_err(|errors| errors.to_string())?;
+
+    // Only asymmetric keys published by the provider are trusted
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err("Unsupported ID token algorithm".to_string());
+    }
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or("Unknown ID token key")?;
+    let decoding_key = DecodingKey::from_jwk(jwk).map_err(|errors| errors.to_string())?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[issuer]);
+    validation.set_required_spec_claims(&["exp", "aud", "iss", "sub"]);
+
+    let token_data = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(token_data.claims)
+}
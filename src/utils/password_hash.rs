@@ -0,0 +1,135 @@
+use std::env;
+
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use rand::RngCore;
+
+// The minimum recommended by OWASP for argon2id
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// How new password hashes are computed. Existing hashes of either kind keep
+/// verifying, and are upgraded on login when they don't match the settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordHashing {
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Bcrypt {
+        cost: u32,
+    },
+}
+
+impl Default for PasswordHashing {
+    fn default() -> Self {
+        PasswordHashing::Argon2id {
+            memory_kib: ARGON2_MEMORY_KIB,
+            iterations: ARGON2_ITERATIONS,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+}
+
+fn env_number(name: &str, default: u32) -> u32 {
+    env::var(name).map_or(default, |value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number", name))
+    })
+}
+
+fn argon2(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Argon2<'static>, String> {
+    let params = Params::new(memory_kib, iterations, parallelism, None)
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+impl PasswordHashing {
+    /// Reads `PASSWORD_HASH` (`argon2id` or `bcrypt`) and the parameters of
+    /// the algorithm.
+    pub fn from_env() -> Self {
+        match env::var("PASSWORD_HASH").as_deref() {
+            Ok("bcrypt") => PasswordHashing::Bcrypt {
+                cost: env_number("BCRYPT_COST", bcrypt::DEFAULT_COST),
+            },
+            Ok("argon2id") | Err(_) => PasswordHashing::Argon2id {
+                memory_kib: env_number("ARGON2_MEMORY_KIB", ARGON2_MEMORY_KIB),
+                iterations: env_number("ARGON2_ITERATIONS", ARGON2_ITERATIONS),
+                parallelism: env_number("ARGON2_PARALLELISM", ARGON2_PARALLELISM),
+            },
+            Ok(other) => panic!("Unknown PASSWORD_HASH: {}", other),
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, String> {
+        match *self {
+            PasswordHashing::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let mut salt = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let salt = SaltString::encode_b64(&salt).map_err(|errors| errors.to_string())?;
+
+                argon2(memory_kib, iterations, parallelism)?
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|errors| errors.to_string())
+            }
+            PasswordHashing::Bcrypt { cost } => {
+                bcrypt::hash(password, cost).map_err(|errors| errors.to_string())
+            }
+        }
+    }
+
+    /// Whether the hash was computed with another algorithm or parameters.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        match *self {
+            PasswordHashing::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let hash = match PasswordHash::new(hash) {
+                    Ok(hash) if hash.algorithm == Algorithm::Argon2id.ident() => hash,
+                    _ => return true,
+                };
+
+                Params::try_from(&hash).map_or(true, |params| {
+                    params.m_cost() != memory_kib
+                        || params.t_cost() != iterations
+                        || params.p_cost() != parallelism
+                })
+            }
+            PasswordHashing::Bcrypt { cost } => bcrypt_cost(hash) != Some(cost),
+        }
+    }
+}
+
+/// Cost of a `$2b$12$...` hash.
+fn bcrypt_cost(hash: &str) -> Option<u32> {
+    hash.strip_prefix("$2")?.split('$').nth(1)?.parse().ok()
+}
+
+/// Checks a password against a hash of any supported algorithm.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).map_err(|errors| errors.to_string());
+    }
+
+    let hash = PasswordHash::new(hash).map_err(|errors| errors.to_string())?;
+
+    // The parameters are read from the hash
+    match Argon2::default().verify_password(password.as_bytes(), &hash) {
+        Ok(()) => Ok(true),
+        Err(password_hash::Error::Password) => Ok(false),
+        Err(errors) => Err(errors.to_string()),
+    }
+}
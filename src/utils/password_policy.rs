@@ -0,0 +1,172 @@
+use std::{env, io::ErrorKind, path::PathBuf, str::FromStr};
+
+use axum::http::StatusCode;
+use sha1::{Digest, Sha1};
+
+/// A kind of character a password can be required to contain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    fn matches(&self, character: char) -> bool {
+        match self {
+            CharacterClass::Lowercase => character.is_lowercase(),
+            CharacterClass::Uppercase => character.is_uppercase(),
+            CharacterClass::Digit => character.is_ascii_digit(),
+            CharacterClass::Symbol => !character.is_alphanumeric() && !character.is_whitespace(),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            CharacterClass::Lowercase => "a lowercase letter",
+            CharacterClass::Uppercase => "an uppercase letter",
+            CharacterClass::Digit => "a digit",
+            CharacterClass::Symbol => "a symbol",
+        }
+    }
+}
+
+impl FromStr for CharacterClass {
+    type Err = String;
+
+    fn from_str(class: &str) -> Result<Self, Self::Err> {
+        match class {
+            "lowercase" => Ok(CharacterClass::Lowercase),
+            "uppercase" => Ok(CharacterClass::Uppercase),
+            "digit" => Ok(CharacterClass::Digit),
+            "symbol" => Ok(CharacterClass::Symbol),
+            _ => Err(format!("Unknown character class: {}", class)),
+        }
+    }
+}
+
+/// Rules every new password must follow, at registration, change or reset.
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub required_classes: Vec<CharacterClass>,
+    /// Directory of a breached password list split by hash prefix, like the
+    /// Pwned Passwords range files: the uppercase SHA-1 of a password is looked
+    /// up as `<first 5 characters>.txt`, holding `<other 35 characters>:<count>`
+    /// lines. Only the file of the prefix is read.
+    pub breached_passwords_dir: Option<PathBuf>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            required_classes: Vec::new(),
+            breached_passwords_dir: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Reads `PASSWORD_MIN_LENGTH`, `PASSWORD_REQUIRED_CLASSES` (a comma
+    /// separated list of `lowercase`, `uppercase`, `digit` and `symbol`) and
+    /// `BREACHED_PASSWORDS_DIR`.
+    pub fn from_env() -> Self {
+        let default = PasswordPolicy::default();
+
+        PasswordPolicy {
+            min_length: env::var("PASSWORD_MIN_LENGTH").map_or(default.min_length, |value| {
+                value.parse().expect("PASSWORD_MIN_LENGTH must be a number")
+            }),
+            required_classes: env::var("PASSWORD_REQUIRED_CLASSES").map_or(
+                default.required_classes,
+                |value| {
+                    value
+                        .split(',')
+                        .filter(|class| !class.trim().is_empty())
+                        .map(|class| {
+                            class
+                                .trim()
+                                .parse()
+                                .expect("PASSWORD_REQUIRED_CLASSES has an unknown class")
+                        })
+                        .collect()
+                },
+            ),
+            breached_passwords_dir: env::var("BREACHED_PASSWORDS_DIR").ok().map(PathBuf::from),
+        }
+    }
+
+    /// Fails with a message naming the first rule the password breaks.
+    pub async fn check(&self, username: &str, password: &str) -> Result<(), (StatusCode, String)> {
+        if password.chars().count() < self.min_length {
+            return Err(policy_error(format!(
+                "Password must have at least {} characters",
+                self.min_length
+            )));
+        }
+
+        if let Some(class) = self
+            .required_classes
+            .iter()
+            .find(|class| !password.chars().any(|character| class.matches(character)))
+        {
+            return Err(policy_error(format!(
+                "Password must contain {}",
+                class.description()
+            )));
+        }
+
+        if password.to_lowercase() == username.to_lowercase() {
+            return Err(policy_error(
+                "Password must not be the username".to_string(),
+            ));
+        }
+
+        if self
+            .is_breached(password)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?
+        {
+            return Err(policy_error(
+                "Password appears in a list of breached passwords".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn is_breached(&self, password: &str) -> Result<bool, String> {
+        let breached_passwords_dir = match &self.breached_passwords_dir {
+            Some(breached_passwords_dir) => breached_passwords_dir,
+            None => return Ok(false),
+        };
+
+        let hash: String = Sha1::digest(password.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let (prefix, suffix) = hash.split_at(5);
+
+        let range =
+            match tokio::fs::read_to_string(breached_passwords_dir.join(format!("{}.txt", prefix)))
+                .await
+            {
+                Ok(range) => range,
+                // No file means no breached password with this prefix
+                Err(errors) if errors.kind() == ErrorKind::NotFound => return Ok(false),
+                Err(errors) => return Err(errors.to_string()),
+            };
+
+        Ok(range.lines().any(|line| {
+            line.split(':')
+                .next()
+                .is_some_and(|line_suffix| line_suffix.trim().eq_ignore_ascii_case(suffix))
+        }))
+    }
+}
+
+fn policy_error(message: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message)
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use sea_orm::prelude::Uuid;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::utils::{
+    jwt::{create_pow_token, decode_pow_token},
+    jwt_keys::JwtKeys,
+};
+
+/// Time given to the client to solve a challenge.
+pub const CHALLENGE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+/// Window the request volume is measured over
+const VOLUME_WINDOW: Duration = Duration::from_secs(60);
+/// Challenges per window handed out at the base difficulty
+const SPIKE_VOLUME: u32 = 60;
+/// Extra bits a spike can add, each doubles the work of the clients
+const MAX_EXTRA_DIFFICULTY: u32 = 6;
+/// Spent challenges are remembered past their expiry for the leeway of the
+/// signature check
+const EXPIRY_LEEWAY_SECONDS: i64 = 60;
+
+/// A solved challenge, sent along with the credentials.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PowSolution {
+    pub challenge: String,
+    pub solution: String,
+}
+
+/// Number of leading zero bits of the hash of `<challenge>:<solution>`.
+pub fn pow_hash_zeros(challenge: &str, solution: &str) -> u32 {
+    let hash = Sha256::digest(format!("{}:{}", challenge, solution).as_bytes());
+
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Issues signed proof-of-work challenges and checks their solutions.
+///
+/// The difficulty rises by one bit each time the number of challenges handed
+/// out in the last minute doubles past `SPIKE_VOLUME`. A solution can only be
+/// used once. Both the volume and the spent challenges live in memory and are
+/// per instance.
+#[derive(Clone)]
+pub struct ProofOfWork {
+    base_difficulty: u32,
+    recent_challenges: Arc<Mutex<VecDeque<Instant>>>,
+    /// Expiry of the challenges already solved, by id.
+    spent_challenges: Arc<Mutex<HashMap<Uuid, i64>>>,
+}
+
+impl ProofOfWork {
+    pub fn new(base_difficulty: u32) -> Self {
+        ProofOfWork {
+            base_difficulty,
+            recent_challenges: Arc::new(Mutex::new(VecDeque::new())),
+            spent_challenges: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Difficulty of the next challenge, counting it in the volume.
+    fn next_difficulty(&self) -> u32 {
+        let mut recent_challenges = self.recent_challenges.lock().unwrap();
+        let now = Instant::now();
+
+        while recent_challenges
+            .front()
+            .is_some_and(|issued_at| now - *issued_at >= VOLUME_WINDOW)
+        {
+            recent_challenges.pop_front();
+        }
+        recent_challenges.push_back(now);
+
+        let volume = recent_challenges.len() as u32;
+        let extra = (volume / SPIKE_VOLUME + 1).ilog2();
+
+        self.base_difficulty + extra.min(MAX_EXTRA_DIFFICULTY)
+    }
+
+    /// A new challenge and its difficulty.
+    pub async fn issue(&self, jwt_keys: &JwtKeys) -> Result<(String, u32), String> {
+        let difficulty = self.next_difficulty();
+        let lifetime =
+            chrono::Duration::from_std(CHALLENGE_LIFETIME).map_err(|errors| errors.to_string())?;
+
+        let challenge = create_pow_token(jwt_keys, difficulty, lifetime).await?;
+
+        Ok((challenge, difficulty))
+    }
+
+    /// Checks the solution and spends its challenge.
+    pub async fn verify(&self, jwt_keys: &JwtKeys, solution: &PowSolution) -> Result<(), String> {
+        let claims = decode_pow_token(jwt_keys, &solution.challenge).await?;
+
+        if pow_hash_zeros(&solution.challenge, &solution.solution) < claims.difficulty {
+            return Err("Proof of work is not solved".to_string());
+        }
+
+        let mut spent_challenges = self.spent_challenges.lock().unwrap();
+        let now = Utc::now().timestamp();
+
+        spent_challenges.retain(|_, expires_at| *expires_at + EXPIRY_LEEWAY_SECONDS >= now);
+
+        if spent_challenges
+            .insert(claims.jti, claims.exp as i64)
+            .is_some()
+        {
+            return Err("Proof of work was already used".to_string());
+        }
+
+        Ok(())
+    }
+}
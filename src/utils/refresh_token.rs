@@ -0,0 +1,100 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use sea_orm::{
+    prelude::Uuid, sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, Set,
+};
+
+use crate::{
+    database::refresh_tokens,
+    middlewares::auth_middleware::unauthorized,
+    utils::opaque_token::{generate_token, hash_token},
+};
+
+pub const REFRESH_TOKEN_DAYS: i64 = 30;
+
+/// Stores a new refresh token in `family` and returns it in clear, pass
+/// `Uuid::new_v4()` to start a new family on login.
+pub async fn issue_refresh_token(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    family: Uuid,
+) -> Result<String, String> {
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_DAYS);
+
+    refresh_tokens::ActiveModel {
+        user_id: Set(user_id),
+        family: Set(family),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(expires_at.into()),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    Ok(token)
+}
+
+/// Exchanges a refresh token for a new one of the same family. A token that
+/// was already rotated being presented again means it leaked, so the whole
+/// family is revoked and both the attacker and the victim have to log in.
+/// Returns the presented token along with the new one.
+pub async fn rotate_refresh_token(
+    database_conn: &DatabaseConnection,
+    token: &str,
+) -> Result<(refresh_tokens::Model, String), (StatusCode, String)> {
+    let stored = refresh_tokens::Entity::find()
+        .filter(refresh_tokens::Column::TokenHash.eq(hash_token(token)))
+        .one(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors.to_string()))?
+        .ok_or_else(unauthorized)?;
+
+    if stored.revoked_at.is_some() || stored.expires_at < Utc::now() {
+        return Err(unauthorized());
+    }
+
+    if stored.used_at.is_some() {
+        revoke_family(database_conn, stored.family)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+        return Err(unauthorized());
+    }
+
+    // Only one of two concurrent rotations of the same token can win this
+    // update, the loser is treated as a reuse.
+    let result = refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(refresh_tokens::Column::Id.eq(stored.id))
+        .filter(refresh_tokens::Column::UsedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors.to_string()))?;
+
+    if result.rows_affected == 0 {
+        revoke_family(database_conn, stored.family)
+            .await
+            .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+        return Err(unauthorized());
+    }
+
+    let token = issue_refresh_token(database_conn, stored.user_id, stored.family)
+        .await
+        .map_err(|errors| (StatusCode::INTERNAL_SERVER_ERROR, errors))?;
+
+    Ok((stored, token))
+}
+
+pub async fn revoke_family(database_conn: &DatabaseConnection, family: Uuid) -> Result<(), String> {
+    refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(refresh_tokens::Column::Family.eq(family))
+        .filter(refresh_tokens::Column::RevokedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use chrono::{Duration, TimeZone, Utc};
+use sea_orm::{
+    prelude::Uuid, sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait,
+    DatabaseConnection, EntityTrait, QueryFilter, Set,
+};
+
+use crate::{
+    database::{refresh_tokens, revoked_tokens, sessions},
+    utils::jwt::{Claims, TOKEN_HOURS},
+};
+
+/// Adds a single access token to the denylist until it expires on its own.
+pub async fn revoke_token(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    jti: Uuid,
+    exp: usize,
+) -> Result<(), String> {
+    let expires_at = Utc
+        .timestamp_opt(exp as i64, 0)
+        .single()
+        .ok_or("Invalid token expiration")?;
+
+    revoked_tokens::ActiveModel {
+        jti: Set(Some(jti)),
+        user_id: Set(user_id),
+        expires_at: Set(expires_at.into()),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
+
+/// Revokes every token issued to the user so far, access and refresh alike,
+/// and ends all their sessions.
+/// The denylist entry has no `jti` and matches any token issued up to now,
+/// so it is kept for as long as the last of them could be valid.
+pub async fn revoke_all_tokens<C: ConnectionTrait>(
+    database_conn: &C,
+    user_id: i32,
+) -> Result<(), String> {
+    let revoked_at = Utc::now();
+
+    revoked_tokens::ActiveModel {
+        jti: Set(None),
+        user_id: Set(user_id),
+        revoked_at: Set(revoked_at.into()),
+        expires_at: Set((revoked_at + Duration::hours(TOKEN_HOURS)).into()),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::RevokedAt, Expr::value(revoked_at))
+        .filter(refresh_tokens::Column::UserId.eq(user_id))
+        .filter(refresh_tokens::Column::RevokedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    sessions::Entity::update_many()
+        .col_expr(sessions::Column::RevokedAt, Expr::value(revoked_at))
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::RevokedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
+
+/// Like `revoke_all_tokens`, but the session `current_sid` goes on with its
+/// tokens, such as after a password change made from it. Every other token of
+/// the user is revoked, those issued without a session too: impersonation
+/// tokens and refresh token families older than sessions.
+pub async fn revoke_other_tokens(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    current_sid: Option<i32>,
+) -> Result<(), String> {
+    let revoked_at = Utc::now();
+
+    revoked_tokens::ActiveModel {
+        jti: Set(None),
+        user_id: Set(user_id),
+        revoked_at: Set(revoked_at.into()),
+        expires_at: Set((revoked_at + Duration::hours(TOKEN_HOURS)).into()),
+        except_sid: Set(current_sid),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    let current_session = match current_sid {
+        Some(sid) => sessions::Entity::find_by_id(sid)
+            .one(database_conn)
+            .await
+            .map_err(|errors| errors.to_string())?,
+        None => None,
+    };
+
+    let mut refresh_tokens_query = refresh_tokens::Entity::update_many()
+        .col_expr(refresh_tokens::Column::RevokedAt, Expr::value(revoked_at))
+        .filter(refresh_tokens::Column::UserId.eq(user_id))
+        .filter(refresh_tokens::Column::RevokedAt.is_null());
+    let mut sessions_query = sessions::Entity::update_many()
+        .col_expr(sessions::Column::RevokedAt, Expr::value(revoked_at))
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::RevokedAt.is_null());
+    if let Some(session) = current_session {
+        refresh_tokens_query =
+            refresh_tokens_query.filter(refresh_tokens::Column::Family.ne(session.refresh_family));
+        sessions_query = sessions_query.filter(sessions::Column::Id.ne(session.id));
+    }
+
+    refresh_tokens_query
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+    sessions_query
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
+
+/// `iat` only has a one second precision, a token issued during the same
+/// second as a revoke-all is considered revoked too. An impersonation token
+/// is also revoked by a revoke-all of the impersonating admin. A revoke-all
+/// with `except_sid` spares the tokens of that session.
+pub async fn is_revoked(
+    database_conn: &DatabaseConnection,
+    claims: &Claims,
+) -> Result<bool, String> {
+    let issued_at = Utc
+        .timestamp_opt(claims.iat as i64, 0)
+        .single()
+        .ok_or("Invalid token issue date")?;
+
+    let mut user_ids = vec![claims.id];
+    if let Some(actor) = claims.act {
+        user_ids.push(actor.sub);
+    }
+
+    let mut revoke_all = Condition::all()
+        .add(revoked_tokens::Column::Jti.is_null())
+        .add(revoked_tokens::Column::UserId.is_in(user_ids))
+        .add(revoked_tokens::Column::RevokedAt.gte(issued_at));
+    if let Some(sid) = claims.sid {
+        revoke_all = revoke_all.add(
+            Condition::any()
+                .add(revoked_tokens::Column::ExceptSid.is_null())
+                .add(revoked_tokens::Column::ExceptSid.ne(sid)),
+        );
+    }
+
+    let revoked = revoked_tokens::Entity::find()
+        .filter(
+            Condition::any()
+                .add(revoked_tokens::Column::Jti.eq(claims.jti))
+                .add(revoke_all),
+        )
+        .one(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(revoked.is_some())
+}
+
+pub async fn prune_revoked_tokens(database_conn: &DatabaseConnection) -> Result<u64, String> {
+    let result = revoked_tokens::Entity::delete_many()
+        .filter(revoked_tokens::Column::ExpiresAt.lt(Utc::now()))
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(result.rows_affected)
+}
+
+/// Runs forever, removing denylist entries of tokens that expired anyway.
+pub async fn prune_revoked_tokens_task(database_conn: DatabaseConnection) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(errors) = prune_revoked_tokens(&database_conn).await {
+            eprintln!("Failed to prune revoked tokens: {}", errors);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// A named capability checked by `require_permission`. Roles are just sets of
+/// these, so adding a role never requires touching the routes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Permission {
+    #[serde(rename = "tasks:read")]
+    ReadTasks,
+    #[serde(rename = "tasks:write")]
+    WriteTasks,
+    #[serde(rename = "users:read")]
+    ReadUsers,
+    #[serde(rename = "users:write")]
+    WriteUsers,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ReadTasks => "tasks:read",
+            Permission::WriteTasks => "tasks:write",
+            Permission::ReadUsers => "users:read",
+            Permission::WriteUsers => "users:write",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(permission: &str) -> Result<Self, Self::Err> {
+        match permission {
+            "tasks:read" => Ok(Permission::ReadTasks),
+            "tasks:write" => Ok(Permission::WriteTasks),
+            "users:read" => Ok(Permission::ReadUsers),
+            "users:write" => Ok(Permission::WriteUsers),
+            _ => Err(format!("Unknown permission: {}", permission)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::User => &[Permission::ReadTasks, Permission::WriteTasks],
+            Role::Admin => &[
+                Permission::ReadTasks,
+                Permission::WriteTasks,
+                Permission::ReadUsers,
+                Permission::WriteUsers,
+            ],
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {}", role)),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    prelude::Uuid, sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, Set,
+};
+
+use crate::{
+    config::Config,
+    database::sessions,
+    utils::{client_ip::ClientIp, refresh_token::revoke_family},
+};
+
+/// `last_seen_at` is only updated when older than this, so that every request
+/// doesn't write to the database.
+const LAST_SEEN_PRECISION_SECONDS: i64 = 60;
+
+const USER_AGENT_MAX_LENGTH: usize = 255;
+
+/// The device a session is started from, shown to the user to recognize it.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    Config: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|header| header.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(USER_AGENT_MAX_LENGTH).collect());
+
+        Ok(ClientInfo { ip, user_agent })
+    }
+}
+
+/// Records a session for a new refresh token family and returns its id, to
+/// bind the access tokens to.
+pub async fn create_session(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    refresh_family: Uuid,
+    client: &ClientInfo,
+) -> Result<i32, String> {
+    let session = sessions::ActiveModel {
+        user_id: Set(user_id),
+        refresh_family: Set(refresh_family),
+        user_agent: Set(client.user_agent.clone()),
+        ip: Set(client.ip.clone()),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    Ok(session.id)
+}
+
+/// Whether the session wasn't revoked, in which case it is marked as seen.
+pub async fn touch_session(database_conn: &DatabaseConnection, id: i32) -> Result<bool, String> {
+    let session = sessions::Entity::find_by_id(id)
+        .one(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    let session = match session {
+        Some(session) if session.revoked_at.is_none() => session,
+        _ => return Ok(false),
+    };
+
+    let now = Utc::now();
+    if now - Duration::seconds(LAST_SEEN_PRECISION_SECONDS) > session.last_seen_at {
+        sessions::Entity::update_many()
+            .col_expr(sessions::Column::LastSeenAt, Expr::value(now))
+            .filter(sessions::Column::Id.eq(id))
+            .exec(database_conn)
+            .await
+            .map_err(|errors| errors.to_string())?;
+    }
+
+    Ok(true)
+}
+
+pub async fn find_session_by_family(
+    database_conn: &DatabaseConnection,
+    refresh_family: Uuid,
+) -> Result<Option<sessions::Model>, String> {
+    sessions::Entity::find()
+        .filter(sessions::Column::RefreshFamily.eq(refresh_family))
+        .one(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())
+}
+
+/// Ends the session, its refresh tokens can't be used anymore and the auth
+/// layer rejects its access tokens.
+pub async fn revoke_session(
+    database_conn: &DatabaseConnection,
+    session: sessions::Model,
+) -> Result<(), String> {
+    sessions::Entity::update_many()
+        .col_expr(sessions::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(sessions::Column::Id.eq(session.id))
+        .filter(sessions::Column::RevokedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    revoke_family(database_conn, session.refresh_family).await
+}
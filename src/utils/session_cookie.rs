@@ -0,0 +1,80 @@
+use axum::http::HeaderMap;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{jwt::TOKEN_HOURS, refresh_token::REFRESH_TOKEN_DAYS};
+
+pub const SESSION_COOKIE: &str = "session";
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// How the tokens of a login are handed to the client, picked with the
+/// `session` query parameter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    /// In the response body, to send in the `Authorization` header.
+    #[default]
+    Bearer,
+    /// In HttpOnly cookies, for browsers.
+    Cookie,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SessionParams {
+    #[serde(default)]
+    pub session: SessionMode,
+}
+
+fn cookie(name: &'static str, value: String, max_age: time::Duration) -> Cookie<'static> {
+    Cookie::build(name, value)
+        .path("/")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .max_age(max_age)
+        .finish()
+}
+
+/// The CSRF cookie is the only one scripts can read, they have to copy it in
+/// the `X-CSRF-Token` header.
+pub fn set_session_cookies(
+    jar: CookieJar,
+    token: String,
+    refresh_token: String,
+    csrf_token: String,
+) -> CookieJar {
+    let mut csrf_cookie = cookie(
+        CSRF_COOKIE,
+        csrf_token,
+        time::Duration::days(REFRESH_TOKEN_DAYS),
+    );
+    csrf_cookie.set_http_only(false);
+
+    jar.add(cookie(
+        SESSION_COOKIE,
+        token,
+        time::Duration::hours(TOKEN_HOURS),
+    ))
+    .add(cookie(
+        REFRESH_COOKIE,
+        refresh_token,
+        time::Duration::days(REFRESH_TOKEN_DAYS),
+    ))
+    .add(csrf_cookie)
+}
+
+pub fn remove_session_cookies(jar: CookieJar) -> CookieJar {
+    [SESSION_COOKIE, REFRESH_COOKIE, CSRF_COOKIE]
+        .into_iter()
+        .fold(jar, |jar, name| {
+            jar.remove(Cookie::build(name, "").path("/").finish())
+        })
+}
+
+pub fn csrf_header(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
@@ -0,0 +1,78 @@
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, Rng};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "axum-webapp";
+const STEP_SECONDS: u64 = 30;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Generates a new base32 secret for the authenticator app.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, username: &str) -> Result<TOTP, String> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|errors| format!("{:?}", errors))?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        STEP_SECONDS,
+        secret,
+        Some(ISSUER.to_string()),
+        username.to_string(),
+    )
+    .map_err(|errors| errors.to_string())
+}
+
+/// `otpauth://` URI to render as a QR code for the authenticator app.
+pub fn otpauth_uri(secret: &str, username: &str) -> Result<String, String> {
+    Ok(totp(secret, username)?.get_url())
+}
+
+/// Returns the time step the code was generated for, allowing one step of
+/// clock skew. Codes of a step at or before `last_step` were already used and
+/// are rejected, which prevents replaying a code seen over someone's shoulder.
+pub fn verify_code(
+    secret: &str,
+    username: &str,
+    code: &str,
+    last_step: Option<i64>,
+) -> Result<Option<i64>, String> {
+    let totp = totp(secret, username)?;
+    let current_step = Utc::now().timestamp() / STEP_SECONDS as i64;
+
+    for step in [current_step - 1, current_step, current_step + 1] {
+        if last_step.is_some_and(|last_step| step <= last_step) {
+            continue;
+        }
+
+        if totp.generate(step as u64 * STEP_SECONDS) == code {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Current code of the secret, for tests and tooling.
+pub fn current_code(secret: &str, username: &str) -> Result<String, String> {
+    Ok(totp(secret, username)?.generate(Utc::now().timestamp() as u64))
+}
+
+/// Recovery codes are shown once, only their hash is stored.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|character| char::from(character).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
@@ -0,0 +1,91 @@
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait,
+};
+
+use crate::{
+    database::{
+        api_keys, recovery_codes, refresh_tokens, revoked_tokens, sessions, tasks, user_identities,
+        user_tokens, users, webauthn_credentials,
+    },
+    utils::audit::{record_audit_event, USER_PURGED},
+};
+
+/// Removes the user and every row referencing it. The audit trail is kept.
+async fn purge_user<C: ConnectionTrait>(database_conn: &C, user_id: i32) -> Result<(), String> {
+    macro_rules! delete_rows_of_user {
+        ($($entity:ident),*) => {
+            $(
+                $entity::Entity::delete_many()
+                    .filter($entity::Column::UserId.eq(user_id))
+                    .exec(database_conn)
+                    .await
+                    .map_err(|errors| errors.to_string())?;
+            )*
+        };
+    }
+
+    delete_rows_of_user!(
+        tasks,
+        refresh_tokens,
+        revoked_tokens,
+        recovery_codes,
+        user_tokens,
+        api_keys,
+        user_identities,
+        sessions,
+        webauthn_credentials
+    );
+
+    users::Entity::delete_by_id(user_id)
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
+
+/// Hard-deletes the users soft-deleted more than `retention_days` ago.
+pub async fn purge_deleted_users(
+    database_conn: &DatabaseConnection,
+    retention_days: i64,
+) -> Result<u64, String> {
+    let deleted_before = Utc::now() - Duration::days(retention_days);
+
+    let users = users::Entity::find()
+        .filter(users::Column::DeletedAt.lt(deleted_before))
+        .all(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    for user in &users {
+        let transaction = database_conn
+            .begin()
+            .await
+            .map_err(|errors| errors.to_string())?;
+        purge_user(&transaction, user.id).await?;
+        transaction
+            .commit()
+            .await
+            .map_err(|errors| errors.to_string())?;
+
+        record_audit_event(database_conn, Some(user.id), None, USER_PURGED, None).await?;
+    }
+
+    Ok(users.len() as u64)
+}
+
+/// Runs forever, purging the accounts past their retention window.
+pub async fn purge_deleted_users_task(database_conn: DatabaseConnection, retention_days: i64) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+
+        match purge_deleted_users(&database_conn, retention_days).await {
+            Ok(0) => {}
+            Ok(count) => println!("Purged {} deleted users", count),
+            Err(errors) => eprintln!("Failed to purge deleted users: {}", errors),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set,
+};
+
+use crate::{
+    database::user_tokens,
+    utils::opaque_token::{generate_token, hash_token},
+};
+
+/// What a single-use token sent by mail allows to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+    MagicLink,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::EmailVerification => "email_verification",
+            TokenPurpose::MagicLink => "magic_link",
+        }
+    }
+}
+
+/// Stores a new token and returns it in clear, to be sent to the user.
+pub async fn issue_user_token(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    purpose: TokenPurpose,
+    lifetime: Duration,
+) -> Result<String, String> {
+    let token = generate_token();
+
+    user_tokens::ActiveModel {
+        user_id: Set(user_id),
+        purpose: Set(purpose.as_str().to_string()),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set((Utc::now() + lifetime).into()),
+        ..Default::default()
+    }
+    .insert(database_conn)
+    .await
+    .map_err(|errors| errors.to_string())?;
+
+    Ok(token)
+}
+
+/// Returns the user of a usable token without using it, `None` when the token
+/// is unknown, expired, already used or was issued for another purpose.
+pub async fn find_user_token(
+    database_conn: &DatabaseConnection,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<i32>, String> {
+    let stored = find_usable_token(database_conn, token, purpose).await?;
+
+    Ok(stored.map(|stored| stored.user_id))
+}
+
+async fn find_usable_token(
+    database_conn: &DatabaseConnection,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<user_tokens::Model>, String> {
+    user_tokens::Entity::find()
+        .filter(user_tokens::Column::TokenHash.eq(hash_token(token)))
+        .filter(user_tokens::Column::Purpose.eq(purpose.as_str()))
+        .filter(user_tokens::Column::UsedAt.is_null())
+        .filter(user_tokens::Column::ExpiresAt.gt(Utc::now()))
+        .one(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())
+}
+
+/// Marks the token as used and returns its user, `None` when the token is
+/// unknown, expired, already used or was issued for another purpose.
+pub async fn consume_user_token(
+    database_conn: &DatabaseConnection,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<i32>, String> {
+    let stored = find_usable_token(database_conn, token, purpose).await?;
+
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return Ok(None),
+    };
+
+    // Conditional so that two concurrent requests can't both use the token
+    let result = user_tokens::Entity::update_many()
+        .col_expr(user_tokens::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(user_tokens::Column::Id.eq(stored.id))
+        .filter(user_tokens::Column::UsedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    if result.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(stored.user_id))
+}
+
+/// Invalidates every pending token of the user for `purpose`.
+pub async fn discard_user_tokens(
+    database_conn: &DatabaseConnection,
+    user_id: i32,
+    purpose: TokenPurpose,
+) -> Result<(), String> {
+    user_tokens::Entity::update_many()
+        .col_expr(user_tokens::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(user_tokens::Column::UserId.eq(user_id))
+        .filter(user_tokens::Column::Purpose.eq(purpose.as_str()))
+        .filter(user_tokens::Column::UsedAt.is_null())
+        .exec(database_conn)
+        .await
+        .map_err(|errors| errors.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use reqwest::Url;
+use ring::signature::{UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_ASN1, ED25519};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::utils::opaque_token::generate_token;
+
+/// Time given to the authenticator to answer a challenge.
+pub const CEREMONY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// COSE identifiers of the supported signature algorithms.
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Registration => "webauthn.create",
+            Ceremony::Authentication => "webauthn.get",
+        }
+    }
+}
+
+struct PendingCeremony {
+    ceremony: Ceremony,
+    user_id: Option<i32>,
+    started_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    client_data_type: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Relying party of the WebAuthn ceremonies, the host of the app.
+///
+/// Challenges are kept in memory until the client answers them, and each can
+/// only be answered once.
+#[derive(Clone)]
+pub struct Webauthn {
+    rp_id: String,
+    origin: String,
+    pending_ceremonies: Arc<Mutex<HashMap<String, PendingCeremony>>>,
+}
+
+impl Webauthn {
+    pub fn new(app_url: &str) -> Self {
+        let app_url = Url::parse(app_url).expect("APP_URL must be a valid URL");
+
+        Webauthn {
+            rp_id: app_url
+                .host_str()
+                .expect("APP_URL must have a host")
+                .to_string(),
+            origin: app_url.origin().ascii_serialization(),
+            pending_ceremonies: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn rp_id(&self) -> &str {
+        &self.rp_id
+    }
+
+    /// Starts a ceremony, for the user registering a credential or for anyone
+    /// logging in, and returns its challenge.
+    pub fn start(&self, ceremony: Ceremony, user_id: Option<i32>) -> String {
+        let challenge = generate_token();
+
+        let mut pending_ceremonies = self.pending_ceremonies.lock().unwrap();
+        pending_ceremonies.retain(|_, pending| pending.started_at.elapsed() < CEREMONY_TIMEOUT);
+        pending_ceremonies.insert(
+            challenge.clone(),
+            PendingCeremony {
+                ceremony,
+                user_id,
+                started_at: Instant::now(),
+            },
+        );
+
+        challenge
+    }
+
+    /// Checks the client data signed by the authenticator and consumes its
+    /// challenge. Returns the user the ceremony was started for.
+    pub fn verify_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: Ceremony,
+    ) -> Result<Option<i32>, String> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).map_err(|errors| errors.to_string())?;
+
+        if client_data.client_data_type != ceremony.client_data_type() {
+            return Err("Unexpected ceremony".to_string());
+        }
+
+        if client_data.origin != self.origin {
+            return Err(format!("Unexpected origin: {}", client_data.origin));
+        }
+
+        let pending = self
+            .pending_ceremonies
+            .lock()
+            .unwrap()
+            .remove(&client_data.challenge)
+            .filter(|pending| {
+                pending.ceremony == ceremony && pending.started_at.elapsed() < CEREMONY_TIMEOUT
+            })
+            .ok_or("Challenge is not valid or expired")?;
+
+        Ok(pending.user_id)
+    }
+}
+
+/// Public key of a credential, in the form `ring` verifies signatures with.
+#[derive(Debug)]
+pub struct CredentialPublicKey {
+    pub algorithm: i64,
+    pub key: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: CredentialPublicKey,
+}
+
+#[derive(Debug)]
+pub struct AuthenticatorData {
+    pub flags: u8,
+    pub sign_count: u32,
+    /// Only present in registrations.
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    /// Parses the data and checks it was made for this relying party, with
+    /// the user present.
+    pub fn parse(bytes: &[u8], rp_id: &str) -> Result<Self, String> {
+        if bytes.len() < 37 {
+            return Err("Authenticator data is too short".to_string());
+        }
+
+        if bytes[..32] != Sha256::digest(rp_id.as_bytes())[..] {
+            return Err("Unexpected relying party".to_string());
+        }
+
+        let flags = bytes[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            return Err("User is not present".to_string());
+        }
+
+        let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+
+        let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            Some(parse_attested_credential(&bytes[37..])?)
+        } else {
+            None
+        };
+
+        Ok(AuthenticatorData {
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+
+    /// Whether the authenticator verified the user, with a PIN or biometrics.
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_USER_VERIFIED != 0
+    }
+}
+
+fn parse_attested_credential(bytes: &[u8]) -> Result<AttestedCredential, String> {
+    // The AAGUID of the authenticator model comes first
+    if bytes.len() < 18 {
+        return Err("Attested credential data is too short".to_string());
+    }
+
+    let length = u16::from_be_bytes([bytes[16], bytes[17]]) as usize;
+    let credential_id = bytes
+        .get(18..18 + length)
+        .ok_or("Attested credential data is too short")?
+        .to_vec();
+
+    // Extensions may follow the key, reading stops at its end
+    let mut public_key = &bytes[18 + length..];
+    let public_key: Value =
+        ciborium::de::from_reader(&mut public_key).map_err(|errors| errors.to_string())?;
+
+    Ok(AttestedCredential {
+        credential_id,
+        public_key: parse_cose_key(&public_key)?,
+    })
+}
+
+fn cose_field(key: &[(Value, Value)], label: i64) -> Option<&Value> {
+    key.iter()
+        .find(|(field, _)| field.as_integer() == Some(label.into()))
+        .map(|(_, value)| value)
+}
+
+fn cose_integer(key: &[(Value, Value)], label: i64) -> Option<i64> {
+    cose_field(key, label)?.as_integer()?.try_into().ok()
+}
+
+fn cose_bytes(key: &[(Value, Value)], label: i64) -> Option<&Vec<u8>> {
+    cose_field(key, label)?.as_bytes()
+}
+
+/// Reads an ES256 or EdDSA key in COSE format.
+fn parse_cose_key(key: &Value) -> Result<CredentialPublicKey, String> {
+    let key = key.as_map().ok_or("Public key is not a COSE key")?;
+
+    // Labels of RFC 9053: 1 is the key type, 3 the algorithm, -1 the curve
+    // and -2 and -3 the coordinates
+    match (
+        cose_integer(key, 1),
+        cose_integer(key, 3),
+        cose_integer(key, -1),
+    ) {
+        (Some(2), Some(ES256), Some(1)) => {
+            let (x, y) = cose_bytes(key, -2)
+                .zip(cose_bytes(key, -3))
+                .filter(|(x, y)| x.len() == 32 && y.len() == 32)
+                .ok_or("Invalid P-256 key")?;
+
+            let mut uncompressed = vec![0x04];
+            uncompressed.extend_from_slice(x);
+            uncompressed.extend_from_slice(y);
+
+            Ok(CredentialPublicKey {
+                algorithm: ES256,
+                key: uncompressed,
+            })
+        }
+        (Some(1), Some(EDDSA), Some(6)) => {
+            let x = cose_bytes(key, -2)
+                .filter(|x| x.len() == 32)
+                .ok_or("Invalid Ed25519 key")?;
+
+            Ok(CredentialPublicKey {
+                algorithm: EDDSA,
+                key: x.clone(),
+            })
+        }
+        _ => Err("Unsupported public key algorithm".to_string()),
+    }
+}
+
+/// Extracts the authenticator data of an attestation object. Only `none`
+/// attestation is requested, the statement itself is not checked.
+pub fn parse_attestation_object(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let attestation: Value =
+        ciborium::de::from_reader(bytes).map_err(|errors| errors.to_string())?;
+
+    attestation
+        .as_map()
+        .and_then(|fields| {
+            fields
+                .iter()
+                .find(|(field, _)| field.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .cloned()
+        .ok_or_else(|| "Attestation object has no authenticator data".to_string())
+}
+
+/// An assertion signs the authenticator data followed by the hash of the
+/// client data.
+pub fn verify_assertion(
+    public_key: &CredentialPublicKey,
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+
+    let algorithm: &dyn VerificationAlgorithm = match public_key.algorithm {
+        ES256 => &ECDSA_P256_SHA256_ASN1,
+        EDDSA => &ED25519,
+        _ => return Err("Unsupported public key algorithm".to_string()),
+    };
+
+    UnparsedPublicKey::new(algorithm, &public_key.key)
+        .verify(&signed, signature)
+        .map_err(|_| "Invalid signature".to_string())
+}
+
+pub fn decode_base64url(value: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|errors| errors.to_string())
+}